@@ -0,0 +1,190 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::f32::consts::{PI, TAU};
+
+/// A biquad filter in transposed direct form II, which stays well behaved when its coefficients
+/// change while it's running.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Biquad {
+    pub coefficients: BiquadCoefficients,
+    s1: f32,
+    s2: f32,
+}
+
+/// Normalized biquad coefficients, computed with the formulas from Robert Bristow-Johnson's Audio
+/// EQ Cookbook.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Default for BiquadCoefficients {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Biquad {
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let BiquadCoefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        let result = b0 * sample + self.s1;
+        self.s1 = b1 * sample - a1 * result + self.s2;
+        self.s2 = b2 * sample - a2 * result;
+
+        result
+    }
+
+    /// Clear the filter's state.
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
+
+impl BiquadCoefficients {
+    /// Passes the signal through unchanged.
+    pub const IDENTITY: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    pub fn highpass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let (cos_omega, alpha) = omega(sample_rate, frequency, q);
+
+        let a0 = 1.0 + alpha;
+        let b0 = (1.0 + cos_omega) / 2.0;
+        let b1 = -(1.0 + cos_omega);
+        let b2 = (1.0 + cos_omega) / 2.0;
+        let a1 = -2.0 * cos_omega;
+        let a2 = 1.0 - alpha;
+
+        Self::normalized(a0, b0, b1, b2, a1, a2)
+    }
+
+    pub fn peak(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let (cos_omega, alpha) = omega(sample_rate, frequency, q);
+        let a = 10.0f32.powf(gain_db / 40.0);
+
+        let a0 = 1.0 + alpha / a;
+        let b0 = 1.0 + alpha * a;
+        let b1 = -2.0 * cos_omega;
+        let b2 = 1.0 - alpha * a;
+        let a1 = -2.0 * cos_omega;
+        let a2 = 1.0 - alpha / a;
+
+        Self::normalized(a0, b0, b1, b2, a1, a2)
+    }
+
+    pub fn low_shelf(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let (cos_omega, alpha) = omega(sample_rate, frequency, q);
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+
+        let a0 = (a + 1.0) + (a - 1.0) * cos_omega + beta;
+        let b0 = a * ((a + 1.0) - (a - 1.0) * cos_omega + beta);
+        let b1 = 2.0 * a * ((a - 1.0) - (a + 1.0) * cos_omega);
+        let b2 = a * ((a + 1.0) - (a - 1.0) * cos_omega - beta);
+        let a1 = -2.0 * ((a - 1.0) + (a + 1.0) * cos_omega);
+        let a2 = (a + 1.0) + (a - 1.0) * cos_omega - beta;
+
+        Self::normalized(a0, b0, b1, b2, a1, a2)
+    }
+
+    pub fn high_shelf(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let (cos_omega, alpha) = omega(sample_rate, frequency, q);
+        let a = 10.0f32.powf(gain_db / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+
+        let a0 = (a + 1.0) - (a - 1.0) * cos_omega + beta;
+        let b0 = a * ((a + 1.0) + (a - 1.0) * cos_omega + beta);
+        let b1 = -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_omega);
+        let b2 = a * ((a + 1.0) + (a - 1.0) * cos_omega - beta);
+        let a1 = 2.0 * ((a - 1.0) - (a + 1.0) * cos_omega);
+        let a2 = (a + 1.0) - (a - 1.0) * cos_omega - beta;
+
+        Self::normalized(a0, b0, b1, b2, a1, a2)
+    }
+
+    /// The first stage of the ITU-R BS.1770 K-weighting filter, a high shelf modelling the head's
+    /// acoustic effects. The analog prototype is matched at any sample rate rather than only using
+    /// the standard's 48 kHz coefficients.
+    pub fn k_weighting_shelf(sample_rate: f32) -> Self {
+        const FREQUENCY: f32 = 1681.974_5;
+        const GAIN_DB: f32 = 3.999_843_8;
+        const Q: f32 = 0.707_175_24;
+
+        let k = (PI * FREQUENCY / sample_rate).tan();
+        let vh = 10.0f32.powf(GAIN_DB / 20.0);
+        let vb = vh.powf(0.499_666_78);
+
+        let a0 = 1.0 + k / Q + k * k;
+        let b0 = vh + vb * k / Q + k * k;
+        let b1 = 2.0 * (k * k - vh);
+        let b2 = vh - vb * k / Q + k * k;
+        let a1 = 2.0 * (k * k - 1.0);
+        let a2 = 1.0 - k / Q + k * k;
+
+        Self::normalized(a0, b0, b1, b2, a1, a2)
+    }
+
+    /// The second stage of the K-weighting filter, the revised low-frequency B-curve high-pass.
+    pub fn k_weighting_highpass(sample_rate: f32) -> Self {
+        const FREQUENCY: f32 = 38.135_47;
+        const Q: f32 = 0.500_327;
+
+        let k = (PI * FREQUENCY / sample_rate).tan();
+
+        let a0 = 1.0 + k / Q + k * k;
+        let a1 = 2.0 * (k * k - 1.0);
+        let a2 = 1.0 - k / Q + k * k;
+
+        // The numerator isn't normalized, the standard's filter has a gain of one at high
+        // frequencies
+        Self {
+            b0: 1.0,
+            b1: -2.0,
+            b2: 1.0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    fn normalized(a0: f32, b0: f32, b1: f32, b2: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// The cosine of the normalized angular frequency and the cookbook's alpha term. The frequency is
+/// kept below Nyquist so the filter stays stable at any sample rate.
+fn omega(sample_rate: f32, frequency: f32, q: f32) -> (f32, f32) {
+    let frequency = frequency.clamp(1.0, sample_rate * 0.49);
+    let omega = TAU * frequency / sample_rate;
+    (omega.cos(), omega.sin() / (2.0 * q.max(0.01)))
+}
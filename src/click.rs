@@ -0,0 +1,210 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use std::f32::consts::TAU;
+
+/// How long a single click rings out.
+const CLICK_LENGTH_MS: f32 = 40.0;
+/// The time constant of a click's exponential decay.
+const CLICK_DECAY_MS: f32 = 8.0;
+
+/// The click's settings. Whether a send hears the click and how loud is set per send.
+#[derive(Params)]
+pub struct ClickParams {
+    #[id = "click_mode"]
+    pub mode: EnumParam<ClickMode>,
+    /// The number of bars clicked after playback starts in [`ClickMode::CountIn`].
+    #[id = "count_in"]
+    pub count_in_bars: IntParam,
+    #[id = "subdivision"]
+    pub subdivision: EnumParam<Subdivision>,
+    /// Play the first beat of every bar higher and louder.
+    #[id = "accent"]
+    pub accent: BoolParam,
+}
+
+impl Default for ClickParams {
+    fn default() -> Self {
+        Self {
+            mode: EnumParam::new("Click Mode", ClickMode::Continuous),
+            count_in_bars: IntParam::new("Count-In", 2, IntRange::Linear { min: 1, max: 8 })
+                .with_unit(" bars"),
+            subdivision: EnumParam::new("Subdivision", Subdivision::Beats),
+            accent: BoolParam::new("Accent Downbeats", true),
+        }
+    }
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClickMode {
+    /// Click for as long as the transport is playing.
+    #[id = "continuous"]
+    #[name = "Continuous"]
+    Continuous,
+    /// Only click for the first few bars after playback starts.
+    #[id = "count_in"]
+    #[name = "Count-In"]
+    CountIn,
+}
+
+/// How many clicks are played per beat of the time signature.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subdivision {
+    #[id = "beats"]
+    #[name = "Beats"]
+    Beats,
+    #[id = "eighths"]
+    #[name = "Eighths"]
+    Eighths,
+    #[id = "triplets"]
+    #[name = "Triplets"]
+    Triplets,
+    #[id = "sixteenths"]
+    #[name = "Sixteenths"]
+    Sixteenths,
+}
+
+impl Subdivision {
+    fn ticks_per_beat(self) -> i64 {
+        match self {
+            Subdivision::Beats => 1,
+            Subdivision::Eighths => 2,
+            Subdivision::Triplets => 3,
+            Subdivision::Sixteenths => 4,
+        }
+    }
+}
+
+/// Generates a mono click that follows the host's transport.
+#[derive(Default)]
+pub struct ClickGenerator {
+    sample_rate: f32,
+    /// The index of the last tick on the subdivision grid, counted from the start of the timeline.
+    /// `None` while the transport is stopped.
+    last_tick: Option<i64>,
+    /// The first tick after playback started, for the count-in.
+    start_tick: i64,
+
+    phase: f32,
+    phase_delta: f32,
+    amplitude: f32,
+    decay: f32,
+    samples_left: usize,
+}
+
+impl ClickGenerator {
+    pub fn initialize(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.decay = (-1.0 / (CLICK_DECAY_MS / 1000.0 * sample_rate)).exp();
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.last_tick = None;
+        self.samples_left = 0;
+    }
+
+    /// Render this block's click to `output`.
+    pub fn render(&mut self, transport: &Transport, params: &ClickParams, output: &mut [f32]) {
+        let (tempo, pos_beats) = match (transport.tempo, transport.pos_beats()) {
+            (Some(tempo), Some(pos_beats)) if transport.playing && tempo > 0.0 => {
+                (tempo, pos_beats)
+            }
+            _ => {
+                // Let the last click ring out
+                self.last_tick = None;
+                for sample in output.iter_mut() {
+                    *sample = self.next_sample();
+                }
+                return;
+            }
+        };
+
+        // Positions are in quarter notes, the time signature's denominator sets the beat length
+        let numerator = transport.time_sig_numerator.unwrap_or(4).max(1) as i64;
+        let denominator = transport.time_sig_denominator.unwrap_or(4).max(1);
+        let ticks_per_beat = params.subdivision.value().ticks_per_beat();
+        let ticks_per_bar = numerator * ticks_per_beat;
+        let tick_length = 4.0 / denominator as f64 / ticks_per_beat as f64;
+        let bar_start = transport.bar_start_pos_beats().unwrap_or(0.0);
+        let beats_per_sample = tempo / 60.0 / self.sample_rate as f64;
+
+        let count_in_ticks = match params.mode.value() {
+            ClickMode::Continuous => None,
+            ClickMode::CountIn => Some(params.count_in_bars.value() as i64 * ticks_per_bar),
+        };
+        let accent = params.accent.value();
+
+        for (sample_idx, sample) in output.iter_mut().enumerate() {
+            let pos = pos_beats + sample_idx as f64 * beats_per_sample;
+            // Ticks are counted from the timeline's start so they keep increasing across bar lines,
+            // the bar start is only used to find the downbeats
+            let ticks = pos / tick_length;
+            let tick = ticks.floor() as i64;
+
+            let new_tick = match self.last_tick {
+                Some(last_tick) => tick != last_tick,
+                // When playback starts exactly on the grid that first tick is clicked, otherwise
+                // the click starts at the next tick
+                None => {
+                    let on_grid = ticks - ticks.floor() < beats_per_sample / tick_length;
+                    self.start_tick = if on_grid { tick } else { tick + 1 };
+                    on_grid
+                }
+            };
+            self.last_tick = Some(tick);
+
+            let counting = match count_in_ticks {
+                Some(count_in_ticks) => tick - self.start_tick < count_in_ticks,
+                None => true,
+            };
+            if new_tick && counting {
+                let tick_in_bar =
+                    (((pos - bar_start) / tick_length).round() as i64).rem_euclid(ticks_per_bar);
+                if tick_in_bar == 0 && accent {
+                    self.trigger(1760.0, 1.0);
+                } else if tick_in_bar % ticks_per_beat == 0 {
+                    self.trigger(1320.0, 0.7);
+                } else {
+                    self.trigger(1320.0, 0.35);
+                }
+            }
+
+            *sample = self.next_sample();
+        }
+    }
+
+    fn trigger(&mut self, frequency: f32, amplitude: f32) {
+        self.phase = 0.0;
+        self.phase_delta = frequency / self.sample_rate * TAU;
+        self.amplitude = amplitude;
+        self.samples_left = (CLICK_LENGTH_MS / 1000.0 * self.sample_rate) as usize;
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        if self.samples_left == 0 {
+            return 0.0;
+        }
+
+        let sample = self.phase.sin() * self.amplitude;
+        self.phase = (self.phase + self.phase_delta) % TAU;
+        self.amplitude *= self.decay;
+        self.samples_left -= 1;
+
+        sample
+    }
+}
@@ -0,0 +1,184 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+
+/// A send's compressor. The IDs are prefixed with `comp_` since they share a namespace with the
+/// other send parameters.
+#[derive(Params)]
+pub struct CompressorParams {
+    #[id = "comp_on"]
+    pub enabled: BoolParam,
+    #[id = "comp_threshold"]
+    pub threshold: FloatParam,
+    #[id = "comp_ratio"]
+    pub ratio: FloatParam,
+    #[id = "comp_attack"]
+    pub attack: FloatParam,
+    #[id = "comp_release"]
+    pub release: FloatParam,
+    /// The width of the soft knee around the threshold.
+    #[id = "comp_knee"]
+    pub knee: FloatParam,
+    #[id = "comp_makeup"]
+    pub makeup: FloatParam,
+}
+
+impl Default for CompressorParams {
+    fn default() -> Self {
+        Self {
+            enabled: BoolParam::new("Compressor", false),
+            threshold: FloatParam::new(
+                "Threshold",
+                -18.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_step_size(0.1),
+            ratio: FloatParam::new(
+                "Ratio",
+                3.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(":1")
+            .with_step_size(0.1),
+            attack: FloatParam::new(
+                "Attack",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 100.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_step_size(0.1),
+            release: FloatParam::new(
+                "Release",
+                150.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_step_size(1.0),
+            knee: FloatParam::new(
+                "Knee",
+                6.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_step_size(0.1),
+            makeup: FloatParam::new(
+                "Makeup Gain",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_step_size(0.1),
+        }
+    }
+}
+
+/// A stereo linked feed-forward compressor with a soft knee. The envelope follows the gain
+/// reduction in decibels, so attack and release behave the same at every level.
+#[derive(Debug, Default)]
+pub struct Compressor {
+    threshold_db: f32,
+    ratio: f32,
+    knee_db: f32,
+    makeup_db: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+
+    /// The current gain reduction in decibels, zero or negative.
+    envelope_db: f32,
+}
+
+impl Compressor {
+    pub fn reset(&mut self) {
+        self.envelope_db = 0.0;
+    }
+
+    /// Pick up parameter changes. Called once at the start of every block.
+    pub fn update(&mut self, params: &CompressorParams, sample_rate: f32) {
+        self.threshold_db = params.threshold.value();
+        self.ratio = params.ratio.value();
+        self.knee_db = params.knee.value();
+        self.makeup_db = params.makeup.value();
+        self.attack_coefficient = time_constant(params.attack.value(), sample_rate);
+        self.release_coefficient = time_constant(params.release.value(), sample_rate);
+    }
+
+    /// Compress a stereo frame. `amount` fades between the dry signal (0) and the compressed signal
+    /// (1), so the compressor can be switched without clicks.
+    pub fn process(&mut self, left: f32, right: f32, amount: f32) -> (f32, f32) {
+        let level_db = util::gain_to_db(left.abs().max(right.abs()));
+        let target_db = self.gain_reduction(level_db);
+
+        let coefficient = if target_db < self.envelope_db {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.envelope_db = target_db + (self.envelope_db - target_db) * coefficient;
+
+        let gain = util::db_to_gain(self.envelope_db + self.makeup_db);
+        let gain = 1.0 + (gain - 1.0) * amount;
+        (left * gain, right * gain)
+    }
+
+    /// The current gain reduction in decibels, as a negative number.
+    pub fn gain_reduction_db(&self) -> f32 {
+        self.envelope_db
+    }
+
+    /// The static gain reduction for a level, following the soft knee curve from Giannoulis,
+    /// Massberg and Reiss' compressor design tutorial.
+    fn gain_reduction(&self, level_db: f32) -> f32 {
+        let overshoot = level_db - self.threshold_db;
+        let slope = 1.0 / self.ratio - 1.0;
+
+        if 2.0 * overshoot < -self.knee_db {
+            0.0
+        } else if self.knee_db > 0.0 && 2.0 * overshoot.abs() <= self.knee_db {
+            let knee_position = overshoot + self.knee_db / 2.0;
+            slope * knee_position * knee_position / (2.0 * self.knee_db)
+        } else {
+            slope * overshoot
+        }
+    }
+}
+
+/// The one pole filter coefficient for a time constant in milliseconds.
+fn time_constant(time_ms: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (time_ms / 1000.0 * sample_rate)).exp()
+}
@@ -0,0 +1,233 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use atomic_float::AtomicF32;
+use nih_plug::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The longest delay a send can be set to.
+pub const MAX_DELAY_MS: f32 = 500.0;
+/// How long it takes to fade from the old to the new delay time when the delay changes.
+const CROSSFADE_MS: f32 = 20.0;
+/// The speed of sound in metres per second at 20 °C, used to convert distances to delay times.
+const SPEED_OF_SOUND: f32 = 343.0;
+
+/// The unit delay times are displayed and entered in. The delay parameters themselves are always
+/// stored in milliseconds.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayUnit {
+    #[id = "ms"]
+    #[name = "Milliseconds"]
+    Milliseconds,
+    #[id = "samples"]
+    #[name = "Samples"]
+    Samples,
+    #[id = "metres"]
+    #[name = "Metres"]
+    Metres,
+}
+
+/// The state the delay parameters' formatters need, shared between the parameters and the plugin.
+pub struct DelayDisplay {
+    /// The index of the selected [`DelayUnit`].
+    unit: AtomicUsize,
+    sample_rate: AtomicF32,
+}
+
+impl Default for DelayDisplay {
+    fn default() -> Self {
+        Self {
+            unit: AtomicUsize::new(DelayUnit::Milliseconds.to_index()),
+            sample_rate: AtomicF32::new(48000.0),
+        }
+    }
+}
+
+impl DelayDisplay {
+    pub fn set_unit(&self, unit: DelayUnit) {
+        self.unit.store(unit.to_index(), Ordering::Relaxed);
+    }
+
+    pub fn set_sample_rate(&self, sample_rate: f32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Format a delay time in milliseconds in the selected unit.
+    pub fn format(&self, delay_ms: f32) -> String {
+        match DelayUnit::from_index(self.unit.load(Ordering::Relaxed)) {
+            DelayUnit::Milliseconds => format!("{delay_ms:.2} ms"),
+            DelayUnit::Samples => format!("{} smp", self.ms_to_samples(delay_ms).round()),
+            DelayUnit::Metres => format!("{:.2} m", delay_ms / 1000.0 * SPEED_OF_SOUND),
+        }
+    }
+
+    /// Parse a delay time to milliseconds. The unit can be given as a suffix, otherwise the
+    /// selected unit is used.
+    pub fn parse(&self, string: &str) -> Option<f32> {
+        let string = string.trim();
+        let (number, unit) = if let Some(number) = string.strip_suffix("samples") {
+            (number, DelayUnit::Samples)
+        } else if let Some(number) = string.strip_suffix("ms") {
+            (number, DelayUnit::Milliseconds)
+        } else if let Some(number) = string.strip_suffix("smp") {
+            (number, DelayUnit::Samples)
+        } else if let Some(number) = string.strip_suffix('m') {
+            (number, DelayUnit::Metres)
+        } else {
+            (
+                string,
+                DelayUnit::from_index(self.unit.load(Ordering::Relaxed)),
+            )
+        };
+
+        let value: f32 = number.trim().parse().ok()?;
+        Some(match unit {
+            DelayUnit::Milliseconds => value,
+            DelayUnit::Samples => value / self.sample_rate.load(Ordering::Relaxed) * 1000.0,
+            DelayUnit::Metres => value / SPEED_OF_SOUND * 1000.0,
+        })
+    }
+
+    fn ms_to_samples(&self, delay_ms: f32) -> f32 {
+        delay_ms / 1000.0 * self.sample_rate.load(Ordering::Relaxed)
+    }
+}
+
+/// A stereo delay line that crossfades between the old and the new delay time when the delay
+/// changes.
+#[derive(Default)]
+pub struct StereoDelay {
+    buffer_l: Vec<f32>,
+    buffer_r: Vec<f32>,
+    write_pos: usize,
+
+    /// The delay in samples that's currently being read from.
+    delay: usize,
+    /// The delay in samples that should be read from. If this differs from `delay`, a crossfade
+    /// to it is started once the current one has finished.
+    target_delay: usize,
+    /// The delay being faded to while `fade_pos < fade_len`.
+    fade_delay: usize,
+    fade_pos: usize,
+    fade_len: usize,
+}
+
+impl StereoDelay {
+    /// Allocate the ring buffers for the maximum delay time at this sample rate. Must not be called
+    /// from the audio thread.
+    pub fn resize(&mut self, sample_rate: f32) {
+        let len = (MAX_DELAY_MS / 1000.0 * sample_rate).ceil() as usize + 1;
+        self.buffer_l.resize(len, 0.0);
+        self.buffer_r.resize(len, 0.0);
+        self.fade_len = (CROSSFADE_MS / 1000.0 * sample_rate).round().max(1.0) as usize;
+        self.reset(self.target_delay);
+    }
+
+    /// Clear the delay lines and jump straight to a new delay time.
+    pub fn reset(&mut self, delay: usize) {
+        self.buffer_l.fill(0.0);
+        self.buffer_r.fill(0.0);
+        self.write_pos = 0;
+        self.delay = self.clamp(delay);
+        self.target_delay = self.delay;
+        self.fade_delay = self.delay;
+        self.fade_pos = self.fade_len;
+    }
+
+    /// Set the delay in samples. The change is faded in over a short crossfade.
+    pub fn set_delay(&mut self, delay: usize) {
+        self.target_delay = self.clamp(delay);
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        if self.buffer_l.is_empty() {
+            return (left, right);
+        }
+
+        self.buffer_l[self.write_pos] = left;
+        self.buffer_r[self.write_pos] = right;
+
+        if self.fade_pos >= self.fade_len && self.target_delay != self.delay {
+            self.fade_delay = self.target_delay;
+            self.fade_pos = 0;
+        }
+
+        let output = if self.fade_pos < self.fade_len {
+            let t = self.fade_pos as f32 / self.fade_len as f32;
+            let (old_l, old_r) = self.read(self.delay);
+            let (new_l, new_r) = self.read(self.fade_delay);
+
+            self.fade_pos += 1;
+            if self.fade_pos == self.fade_len {
+                self.delay = self.fade_delay;
+            }
+
+            (old_l + (new_l - old_l) * t, old_r + (new_r - old_r) * t)
+        } else {
+            self.read(self.delay)
+        };
+
+        self.write_pos = (self.write_pos + 1) % self.buffer_l.len();
+        output
+    }
+
+    fn read(&self, delay: usize) -> (f32, f32) {
+        let len = self.buffer_l.len();
+        let read_pos = (self.write_pos + len - delay) % len;
+        (self.buffer_l[read_pos], self.buffer_r[read_pos])
+    }
+
+    fn clamp(&self, delay: usize) -> usize {
+        delay.min(self.buffer_l.len().saturating_sub(1))
+    }
+}
+
+/// A stereo delay line with a fixed length, used to compensate for the limiter's lookahead.
+#[derive(Default)]
+pub struct FixedDelay {
+    buffer_l: Vec<f32>,
+    buffer_r: Vec<f32>,
+    pos: usize,
+}
+
+impl FixedDelay {
+    /// Allocate the delay line for a delay of `delay` samples. Must not be called from the audio
+    /// thread.
+    pub fn resize(&mut self, delay: usize) {
+        self.buffer_l.resize(delay, 0.0);
+        self.buffer_r.resize(delay, 0.0);
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer_l.fill(0.0);
+        self.buffer_r.fill(0.0);
+        self.pos = 0;
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        if self.buffer_l.is_empty() {
+            return (left, right);
+        }
+
+        let output = (self.buffer_l[self.pos], self.buffer_r[self.pos]);
+        self.buffer_l[self.pos] = left;
+        self.buffer_r[self.pos] = right;
+        self.pos = (self.pos + 1) % self.buffer_l.len();
+
+        output
+    }
+}
@@ -0,0 +1,560 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use nih_plug_egui::egui::{self, Color32, Rect, Sense, Stroke, Ui, Vec2};
+use nih_plug_egui::resizable_window::ResizableWindow;
+use nih_plug_egui::widgets::ParamSlider;
+use nih_plug_egui::{create_egui_editor, EguiState};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+use crate::meters::{LevelMeter, Meters};
+use crate::midi::{self, MidiMapping};
+use crate::scenes::{self, Scene, ScopeGroup};
+use crate::send::SendControl;
+use crate::setlist::{PositionUnit, SetlistEntry};
+use crate::{osc, web, MonitoringSenderParams};
+
+/// The editor's initial size. The window can be resized, the size is saved with the project.
+const DEFAULT_WIDTH: u32 = 1000;
+const DEFAULT_HEIGHT: u32 = 520;
+const MIN_SIZE: Vec2 = Vec2::new(480.0, 420.0);
+
+const STRIP_WIDTH: f32 = 96.0;
+const FADER_HEIGHT: f32 = 180.0;
+const METER_WIDTH: f32 = 12.0;
+
+/// The range shown on the level meters.
+const METER_MIN_DB: f32 = -60.0;
+const METER_MAX_DB: f32 = 6.0;
+
+const METER_BACKGROUND: Color32 = Color32::from_gray(24);
+const METER_RMS: Color32 = Color32::from_rgb(40, 150, 70);
+const METER_PEAK: Color32 = Color32::from_rgb(90, 210, 110);
+const METER_HOLD: Color32 = Color32::from_rgb(240, 200, 60);
+const METER_OVER: Color32 = Color32::from_rgb(230, 60, 50);
+const MUTE_ACTIVE: Color32 = Color32::from_rgb(200, 60, 50);
+const SOLO_ACTIVE: Color32 = Color32::from_rgb(220, 180, 40);
+
+/// The editor's own state, which isn't saved.
+struct EditorState {
+    /// The slot the Store and Recall buttons act on. This is separate from the scene parameter
+    /// because changing that parameter recalls the scene.
+    scene_slot: usize,
+    /// The name and fade time the next scene gets stored with.
+    scene_name: String,
+    scene_fade_ms: f32,
+    /// The send and control waiting for a MIDI controller to be learned.
+    learning: Option<(usize, SendControl)>,
+    /// The number of controllers received when learning started. The next controller received
+    /// after that gets learned.
+    learn_after: u32,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        Self {
+            scene_slot: 0,
+            scene_name: String::new(),
+            scene_fade_ms: scenes::DEFAULT_FADE_MS,
+            learning: None,
+            learn_after: 0,
+        }
+    }
+}
+
+pub fn default_state() -> Arc<EguiState> {
+    EguiState::from_size(DEFAULT_WIDTH, DEFAULT_HEIGHT)
+}
+
+pub fn create(params: Arc<MonitoringSenderParams>, meters: Arc<Meters>) -> Option<Box<dyn Editor>> {
+    let egui_state = params.editor_state.clone();
+    create_egui_editor(
+        params.editor_state.clone(),
+        EditorState::default(),
+        |_, _| {},
+        move |egui_ctx, setter, state| {
+            learn_midi(&params, state);
+
+            ResizableWindow::new("monitoring-sender")
+                .min_size(MIN_SIZE)
+                .show(egui_ctx, egui_state.as_ref(), |ui| {
+                    ui.horizontal_top(|ui| {
+                        master_section(ui, setter, &params, &meters, state);
+                        ui.separator();
+                        egui::ScrollArea::horizontal().show(ui, |ui| {
+                            ui.horizontal_top(|ui| {
+                                let num_sends = meters.num_sends.load(Ordering::Relaxed);
+                                for send_index in 0..num_sends {
+                                    send_strip(ui, setter, &params, &meters, state, send_index);
+                                }
+                            });
+                        });
+                    });
+                });
+
+            // The meters keep moving without any user input
+            egui_ctx.request_repaint();
+        },
+    )
+}
+
+/// The global settings and the main input's meter.
+fn master_section(
+    ui: &mut Ui,
+    setter: &ParamSetter,
+    params: &MonitoringSenderParams,
+    meters: &Meters,
+    state: &mut EditorState,
+) {
+    ui.vertical(|ui| {
+        ui.set_width(220.0);
+        ui.heading("Master");
+
+        ui.horizontal(|ui| {
+            stereo_meter(ui, &meters.input);
+            ui.vertical(|ui| {
+                ui.label("Input");
+                ui.label(format!(
+                    "{:.1} dB",
+                    meters.input[0]
+                        .peak_db
+                        .load(Ordering::Relaxed)
+                        .max(meters.input[1].peak_db.load(Ordering::Relaxed))
+                ));
+            });
+        });
+
+        ui.label("Main Output");
+        ui.add(ParamSlider::for_param(&params.main_output_mode, setter));
+        ui.label("Listen Send");
+        ui.add(ParamSlider::for_param(&params.listen_send, setter));
+        ui.label("Smoothing Time");
+        ui.add(ParamSlider::for_param(&params.smoothing_time, setter));
+        ui.label("Delay Unit");
+        ui.add(ParamSlider::for_param(&params.delay_unit, setter));
+
+        ui.separator();
+        push_button(ui, setter, &params.talk, "Talk", MUTE_ACTIVE);
+        ui.label("Talkback Dim");
+        ui.add(ParamSlider::for_param(&params.talkback_dim, setter));
+
+        scene_section(ui, setter, params, state);
+        setlist_section(ui, params);
+
+        ui.separator();
+        if ui.button("Reset Loudness").clicked() {
+            meters.reset_loudness();
+        }
+
+        if let Some((send_index, control)) = state.learning {
+            ui.separator();
+            ui.label(format!(
+                "Move a MIDI controller to map it to {} {}",
+                params.send_name(send_index),
+                control.name()
+            ));
+            if ui.button("Cancel").clicked() {
+                state.learning = None;
+            }
+        }
+
+        ui.separator();
+        server_settings(ui, "OSC Server", &params.osc_port, osc::DEFAULT_PORT);
+        server_settings(ui, "Web Mixer", &params.web_port, web::DEFAULT_PORT);
+    });
+}
+
+/// Selecting a scene with the parameter recalls it. Storing a scene overwrites the slot picked
+/// below the parameter with the sends' current values, without recalling anything.
+fn scene_section(
+    ui: &mut Ui,
+    setter: &ParamSetter,
+    params: &MonitoringSenderParams,
+    state: &mut EditorState,
+) {
+    ui.separator();
+    ui.label("Scene");
+    ui.add(ParamSlider::for_param(&params.scene, setter));
+
+    ui.horizontal(|ui| {
+        ui.label("Slot");
+        let mut scene_number = state.scene_slot + 1;
+        ui.add(egui::DragValue::new(&mut scene_number).range(1..=scenes::NUM_SCENES));
+        state.scene_slot = scene_number - 1;
+    });
+
+    let scene_index = state.scene_slot;
+    if let Ok(scenes) = params.scenes.read() {
+        let label = match scenes.get(scene_index) {
+            Some(Some(scene)) if params.scene_state.current() == Some(scene_index) => {
+                format!("Current: {}", scene.name)
+            }
+            Some(Some(scene)) => format!("Stored: {}", scene.name),
+            _ => String::from("Empty"),
+        };
+        ui.label(label);
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        ui.text_edit_singleline(&mut state.scene_name);
+    });
+    ui.horizontal(|ui| {
+        ui.label("Fade");
+        ui.add(
+            egui::DragValue::new(&mut state.scene_fade_ms)
+                .range(0.0..=scenes::MAX_FADE_MS)
+                .speed(10.0)
+                .suffix(" ms"),
+        );
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Store").clicked() {
+            let name = if state.scene_name.is_empty() {
+                format!("Scene {}", scene_index + 1)
+            } else {
+                state.scene_name.clone()
+            };
+            let scene = Scene::capture(name, state.scene_fade_ms, &params.sends, &params.overrides);
+            if let Ok(mut scenes) = params.scenes.write() {
+                if let Some(slot) = scenes.get_mut(scene_index) {
+                    *slot = Some(scene);
+                }
+            }
+        }
+        if ui.button("Recall").clicked() {
+            params.scene_state.request_recall(scene_index);
+        }
+    });
+}
+
+/// The scenes recalled at positions on the host's timeline while it's playing.
+fn setlist_section(ui: &mut Ui, params: &MonitoringSenderParams) {
+    egui::CollapsingHeader::new("Setlist").show(ui, |ui| {
+        let Ok(mut setlist) = params.setlist.write() else {
+            return;
+        };
+
+        ui.checkbox(&mut setlist.enabled, "Follow Transport");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut setlist.unit, PositionUnit::Bars, "Bars");
+            ui.radio_value(&mut setlist.unit, PositionUnit::Seconds, "Seconds");
+        });
+
+        let mut edited = false;
+        let mut removed = None;
+        for (entry_index, entry) in setlist.entries.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let position = ui.add(
+                    egui::DragValue::new(&mut entry.position)
+                        .range(0.0..=f64::MAX)
+                        .speed(0.1)
+                        .max_decimals(2),
+                );
+                edited |= position.drag_stopped() || position.lost_focus();
+
+                let mut scene_number = entry.scene_index + 1;
+                ui.label("Scene");
+                ui.add(egui::DragValue::new(&mut scene_number).range(1..=scenes::NUM_SCENES));
+                entry.scene_index = scene_number - 1;
+
+                if ui.button("Remove").clicked() {
+                    removed = Some(entry_index);
+                }
+            });
+        }
+        if let Some(entry_index) = removed {
+            setlist.entries.remove(entry_index);
+        }
+
+        if ui.button("Add").clicked() {
+            let last = setlist.entries.last().copied();
+            setlist.entries.push(SetlistEntry {
+                position: last.map_or(1.0, |entry| entry.position + 1.0),
+                scene_index: last.map_or(0, |entry| (entry.scene_index + 1) % scenes::NUM_SCENES),
+            });
+            edited = true;
+        }
+        // Sorting while a position is being dragged would move the row away from the cursor
+        if edited {
+            setlist.sort();
+        }
+    });
+}
+
+/// A remote control server's port. Like the servers themselves, changes are picked up the next
+/// time the plugin is activated.
+fn server_settings(ui: &mut Ui, label: &str, port: &RwLock<Option<u16>>, default_port: u16) {
+    let Ok(mut port) = port.write() else {
+        return;
+    };
+
+    ui.horizontal(|ui| {
+        let mut enabled = port.is_some();
+        if ui.checkbox(&mut enabled, label).changed() {
+            *port = enabled.then_some(default_port);
+        }
+        if let Some(port) = port.as_mut() {
+            ui.add(egui::DragValue::new(port).range(1024..=u16::MAX));
+        }
+    });
+}
+
+/// A single send's fader strip.
+fn send_strip(
+    ui: &mut Ui,
+    setter: &ParamSetter,
+    params: &MonitoringSenderParams,
+    meters: &Meters,
+    state: &mut EditorState,
+    send_index: usize,
+) {
+    let send_params = &params.sends[send_index];
+    let send_meters = &meters.sends[send_index];
+
+    ui.group(|ui| {
+        ui.vertical(|ui| {
+            ui.set_width(STRIP_WIDTH);
+
+            let mut name = params.send_name(send_index);
+            if ui
+                .add(egui::TextEdit::singleline(&mut name).desired_width(STRIP_WIDTH))
+                .changed()
+            {
+                params.set_send_name(send_index, name);
+            }
+
+            ui.horizontal(|ui| {
+                stereo_meter(ui, &send_meters.channels);
+                fader(ui, setter, &send_params.gain);
+            });
+            ui.label(send_params.gain.to_string());
+            ui.label(format!(
+                "{:.1} LUFS",
+                send_meters.loudness.short_term_lufs.load(Ordering::Relaxed)
+            ));
+            ui.label(format!(
+                "GR {:.1} dB",
+                send_meters.gain_reduction_db.load(Ordering::Relaxed)
+            ));
+
+            ui.horizontal(|ui| {
+                toggle(ui, setter, &send_params.mute, "M", MUTE_ACTIVE);
+                toggle(ui, setter, &send_params.solo, "S", SOLO_ACTIVE);
+            });
+
+            ui.label("Pan");
+            ui.add(ParamSlider::for_param(&send_params.pan, setter).with_width(STRIP_WIDTH));
+            ui.label("Width");
+            ui.add(ParamSlider::for_param(&send_params.width, setter).with_width(STRIP_WIDTH));
+
+            ui.horizontal(|ui| {
+                ui.menu_button("MIDI", |ui| midi_menu(ui, params, state, send_index));
+                ui.menu_button("Recall", |ui| recall_menu(ui, params, send_index));
+            });
+        });
+    });
+}
+
+/// The MIDI mappings for a send's controls, with buttons to learn and clear them.
+fn midi_menu(
+    ui: &mut Ui,
+    params: &MonitoringSenderParams,
+    state: &mut EditorState,
+    send_index: usize,
+) {
+    for control in SendControl::all() {
+        ui.horizontal(|ui| {
+            let mapping = params.midi_mappings.read().ok().and_then(|mappings| {
+                mappings
+                    .iter()
+                    .find(|mapping| mapping.send_index == send_index && mapping.control == control)
+                    .map(|mapping| mapping.source.describe())
+            });
+            ui.label(format!(
+                "{}: {}",
+                control.name(),
+                mapping.as_deref().unwrap_or("-")
+            ));
+
+            if ui.button("Learn").clicked() {
+                state.learning = Some((send_index, control));
+                state.learn_after = params.midi.last_received().0;
+                ui.close_menu();
+            }
+            if mapping.is_some() && ui.button("Clear").clicked() {
+                if let Ok(mut mappings) = params.midi_mappings.write() {
+                    mappings.retain(|mapping| {
+                        mapping.send_index != send_index || mapping.control != control
+                    });
+                }
+            }
+        });
+    }
+}
+
+/// A send's recall safe flag and the controls scene recalls may change.
+fn recall_menu(ui: &mut Ui, params: &MonitoringSenderParams, send_index: usize) {
+    let Ok(mut recall_scopes) = params.recall_scopes.write() else {
+        return;
+    };
+    let Some(scope) = recall_scopes.get_mut(send_index) else {
+        return;
+    };
+
+    ui.checkbox(&mut scope.safe, "Recall Safe");
+    ui.separator();
+    ui.add_enabled_ui(!scope.safe, |ui| {
+        for group in ScopeGroup::all() {
+            let mut included = scope.contains(group);
+            if ui.checkbox(&mut included, group.name()).changed() {
+                scope.set(group, included);
+            }
+        }
+    });
+}
+
+/// Map the controller the audio thread received last, if one arrived since learning started.
+fn learn_midi(params: &MonitoringSenderParams, state: &mut EditorState) {
+    let Some((send_index, control)) = state.learning else {
+        return;
+    };
+    let (received, source) = params.midi.last_received();
+    if received == state.learn_after {
+        return;
+    }
+
+    if let Ok(mut mappings) = params.midi_mappings.write() {
+        midi::learn(
+            &mut mappings,
+            MidiMapping {
+                source,
+                send_index,
+                control,
+            },
+        );
+    }
+    state.learning = None;
+}
+
+/// A vertical fader for a parameter. Dragging the fader is reported to the host as a single
+/// gesture.
+fn fader(ui: &mut Ui, setter: &ParamSetter, param: &FloatParam) {
+    let mut normalized = param.unmodulated_normalized_value();
+    let response = ui.add_sized(
+        Vec2::new(24.0, FADER_HEIGHT),
+        egui::Slider::new(&mut normalized, 0.0..=1.0)
+            .vertical()
+            .show_value(false),
+    );
+
+    if response.drag_started() {
+        setter.begin_set_parameter(param);
+    }
+    if response.changed() {
+        if response.dragged() {
+            setter.set_parameter_normalized(param, normalized);
+        } else {
+            // Clicks and keyboard input don't start a drag
+            setter.begin_set_parameter(param);
+            setter.set_parameter_normalized(param, normalized);
+            setter.end_set_parameter(param);
+        }
+    }
+    if response.drag_stopped() {
+        setter.end_set_parameter(param);
+    }
+    if response.double_clicked() {
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, param.default_plain_value());
+        setter.end_set_parameter(param);
+    }
+}
+
+/// A button that toggles a boolean parameter and lights up while it's enabled.
+fn toggle(ui: &mut Ui, setter: &ParamSetter, param: &BoolParam, label: &str, active: Color32) {
+    let enabled = param.value();
+    let mut button = egui::Button::new(label);
+    if enabled {
+        button = button.fill(active);
+    }
+
+    if ui.add(button).clicked() {
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, !enabled);
+        setter.end_set_parameter(param);
+    }
+}
+
+/// A button that enables a boolean parameter only while it's held down, like the talkback button
+/// on a desk.
+fn push_button(ui: &mut Ui, setter: &ParamSetter, param: &BoolParam, label: &str, active: Color32) {
+    let mut button = egui::Button::new(label).sense(Sense::drag());
+    if param.value() {
+        button = button.fill(active);
+    }
+
+    // Widgets that only sense drags start dragging as soon as the pointer is pressed on them
+    let response = ui.add(button);
+    if response.drag_started() {
+        setter.begin_set_parameter(param);
+        setter.set_parameter(param, true);
+    }
+    if response.drag_stopped() {
+        setter.set_parameter(param, false);
+        setter.end_set_parameter(param);
+    }
+}
+
+fn stereo_meter(ui: &mut Ui, channels: &[LevelMeter; 2]) {
+    ui.spacing_mut().item_spacing.x = 2.0;
+    for channel in channels {
+        level_meter(ui, channel);
+    }
+}
+
+/// A vertical bar showing a channel's RMS level, its peak level and the held peak.
+fn level_meter(ui: &mut Ui, meter: &LevelMeter) {
+    let (rect, _) = ui.allocate_exact_size(Vec2::new(METER_WIDTH, FADER_HEIGHT), Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0.0, METER_BACKGROUND);
+
+    let peak_db = meter.peak_db.load(Ordering::Relaxed);
+    let bar = |level_db: f32| {
+        let top = rect.bottom() - rect.height() * meter_position(level_db);
+        Rect::from_min_max(egui::pos2(rect.left(), top), rect.right_bottom())
+    };
+    painter.rect_filled(bar(peak_db), 0.0, METER_PEAK);
+    painter.rect_filled(bar(meter.rms_db.load(Ordering::Relaxed)), 0.0, METER_RMS);
+
+    let hold_db = meter.peak_hold_db.load(Ordering::Relaxed);
+    if hold_db > METER_MIN_DB {
+        let y = rect.bottom() - rect.height() * meter_position(hold_db);
+        let color = if hold_db > 0.0 {
+            METER_OVER
+        } else {
+            METER_HOLD
+        };
+        painter.hline(rect.x_range(), y, Stroke::new(2.0, color));
+    }
+}
+
+/// Where a level lies on the meter, from 0 at the bottom to 1 at the top.
+fn meter_position(level_db: f32) -> f32 {
+    ((level_db - METER_MIN_DB) / (METER_MAX_DB - METER_MIN_DB)).clamp(0.0, 1.0)
+}
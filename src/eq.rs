@@ -0,0 +1,444 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::biquad::{Biquad, BiquadCoefficients};
+use crate::send::ControlParam;
+
+pub const NUM_PEAKS: usize = 3;
+/// The high-pass, the low shelf, the peaking bands and the high shelf.
+const NUM_FILTERS: usize = NUM_PEAKS + 3;
+
+/// A send's EQ. The IDs are prefixed with `eq_` since they share a namespace with the other send
+/// parameters.
+#[derive(Params)]
+pub struct EqParams {
+    #[id = "eq_hpf"]
+    pub highpass: BoolParam,
+    #[id = "eq_hpf_freq"]
+    pub highpass_frequency: FloatParam,
+
+    #[id = "eq_ls_freq"]
+    pub low_shelf_frequency: FloatParam,
+    #[id = "eq_ls_gain"]
+    pub low_shelf_gain: FloatParam,
+
+    #[nested(array, group = "Peak")]
+    pub peaks: [PeakParams; NUM_PEAKS],
+
+    #[id = "eq_hs_freq"]
+    pub high_shelf_frequency: FloatParam,
+    #[id = "eq_hs_gain"]
+    pub high_shelf_gain: FloatParam,
+}
+
+#[derive(Params)]
+pub struct PeakParams {
+    #[id = "eq_peak_freq"]
+    pub frequency: FloatParam,
+    #[id = "eq_peak_gain"]
+    pub gain: FloatParam,
+    #[id = "eq_peak_q"]
+    pub q: FloatParam,
+}
+
+impl Default for EqParams {
+    fn default() -> Self {
+        const PEAK_FREQUENCIES: [f32; NUM_PEAKS] = [250.0, 1000.0, 4000.0];
+
+        Self {
+            highpass: BoolParam::new("High-Pass", false),
+            highpass_frequency: frequency_param("High-Pass Frequency", 80.0),
+            low_shelf_frequency: frequency_param("Low Shelf Frequency", 120.0),
+            low_shelf_gain: eq_gain_param("Low Shelf Gain"),
+            peaks: PEAK_FREQUENCIES.map(|frequency| PeakParams {
+                frequency: frequency_param("Frequency", frequency),
+                gain: eq_gain_param("Gain"),
+                q: FloatParam::new(
+                    "Q",
+                    FRAC_1_SQRT_2,
+                    FloatRange::Skewed {
+                        min: 0.1,
+                        max: 10.0,
+                        factor: FloatRange::skew_factor(-1.0),
+                    },
+                )
+                .with_value_to_string(formatters::v2s_f32_rounded(2)),
+            }),
+            high_shelf_frequency: frequency_param("High Shelf Frequency", 8000.0),
+            high_shelf_gain: eq_gain_param("High Shelf Gain"),
+        }
+    }
+}
+
+/// The EQ settings that can be controlled from outside of the host, as part of a send's controls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EqControl {
+    Highpass,
+    HighpassFrequency,
+    LowShelfFrequency,
+    LowShelfGain,
+    /// A peaking band's settings, indexed from 0.
+    PeakFrequency(usize),
+    PeakGain(usize),
+    PeakQ(usize),
+    HighShelfFrequency,
+    HighShelfGain,
+}
+
+/// The number of [`EqControl`]s, counting every peaking band's settings.
+pub const NUM_EQ_CONTROLS: usize = 6 + 3 * NUM_PEAKS;
+
+impl EqControl {
+    /// Every control, in a stable order matching [`index()`][Self::index()].
+    pub fn all() -> impl Iterator<Item = EqControl> {
+        [
+            EqControl::Highpass,
+            EqControl::HighpassFrequency,
+            EqControl::LowShelfFrequency,
+            EqControl::LowShelfGain,
+            EqControl::HighShelfFrequency,
+            EqControl::HighShelfGain,
+        ]
+        .into_iter()
+        .chain((0..NUM_PEAKS).flat_map(|peak_index| {
+            [
+                EqControl::PeakFrequency(peak_index),
+                EqControl::PeakGain(peak_index),
+                EqControl::PeakQ(peak_index),
+            ]
+        }))
+    }
+
+    /// A unique index below [`NUM_EQ_CONTROLS`], or `None` for a band that doesn't exist.
+    pub fn index(self) -> Option<usize> {
+        match self {
+            EqControl::Highpass => Some(0),
+            EqControl::HighpassFrequency => Some(1),
+            EqControl::LowShelfFrequency => Some(2),
+            EqControl::LowShelfGain => Some(3),
+            EqControl::HighShelfFrequency => Some(4),
+            EqControl::HighShelfGain => Some(5),
+            EqControl::PeakFrequency(peak_index) if peak_index < NUM_PEAKS => {
+                Some(6 + peak_index * 3)
+            }
+            EqControl::PeakGain(peak_index) if peak_index < NUM_PEAKS => Some(7 + peak_index * 3),
+            EqControl::PeakQ(peak_index) if peak_index < NUM_PEAKS => Some(8 + peak_index * 3),
+            EqControl::PeakFrequency(_) | EqControl::PeakGain(_) | EqControl::PeakQ(_) => None,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            EqControl::Highpass => String::from("High-Pass"),
+            EqControl::HighpassFrequency => String::from("High-Pass Frequency"),
+            EqControl::LowShelfFrequency => String::from("Low Shelf Frequency"),
+            EqControl::LowShelfGain => String::from("Low Shelf Gain"),
+            EqControl::PeakFrequency(peak_index) => format!("Peak {} Frequency", peak_index + 1),
+            EqControl::PeakGain(peak_index) => format!("Peak {} Gain", peak_index + 1),
+            EqControl::PeakQ(peak_index) => format!("Peak {} Q", peak_index + 1),
+            EqControl::HighShelfFrequency => String::from("High Shelf Frequency"),
+            EqControl::HighShelfGain => String::from("High Shelf Gain"),
+        }
+    }
+
+    /// The parameter this control changes, or `None` for a band that doesn't exist.
+    pub fn param(self, params: &EqParams) -> Option<ControlParam<'_>> {
+        Some(match self {
+            EqControl::Highpass => ControlParam::Bool(&params.highpass),
+            EqControl::HighpassFrequency => ControlParam::Float(&params.highpass_frequency),
+            EqControl::LowShelfFrequency => ControlParam::Float(&params.low_shelf_frequency),
+            EqControl::LowShelfGain => ControlParam::Float(&params.low_shelf_gain),
+            EqControl::PeakFrequency(peak_index) => {
+                ControlParam::Float(&params.peaks.get(peak_index)?.frequency)
+            }
+            EqControl::PeakGain(peak_index) => {
+                ControlParam::Float(&params.peaks.get(peak_index)?.gain)
+            }
+            EqControl::PeakQ(peak_index) => ControlParam::Float(&params.peaks.get(peak_index)?.q),
+            EqControl::HighShelfFrequency => ControlParam::Float(&params.high_shelf_frequency),
+            EqControl::HighShelfGain => ControlParam::Float(&params.high_shelf_gain),
+        })
+    }
+}
+
+fn frequency_param(name: &str, default: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        default,
+        FloatRange::Skewed {
+            min: 20.0,
+            max: 20_000.0,
+            factor: FloatRange::skew_factor(-2.0),
+        },
+    )
+    .with_value_to_string(formatters::v2s_f32_hz_then_khz(0))
+    .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+}
+
+fn eq_gain_param(name: &str) -> FloatParam {
+    FloatParam::new(
+        name,
+        0.0,
+        FloatRange::Linear {
+            min: -18.0,
+            max: 18.0,
+        },
+    )
+    .with_unit(" dB")
+    .with_step_size(0.1)
+}
+
+/// The kinds of filters in the EQ.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FilterType {
+    Highpass,
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+/// A filter's settings. The high-pass ignores the gain, and it and the shelves use a fixed Q.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FilterSettings {
+    frequency: f32,
+    q: f32,
+    gain_db: f32,
+}
+
+impl FilterType {
+    fn coefficients(self, sample_rate: f32, settings: FilterSettings) -> BiquadCoefficients {
+        let FilterSettings {
+            frequency,
+            q,
+            gain_db,
+        } = settings;
+        match self {
+            FilterType::Highpass => BiquadCoefficients::highpass(sample_rate, frequency, q),
+            FilterType::LowShelf => {
+                BiquadCoefficients::low_shelf(sample_rate, frequency, q, gain_db)
+            }
+            FilterType::Peak => BiquadCoefficients::peak(sample_rate, frequency, q, gain_db),
+            FilterType::HighShelf => {
+                BiquadCoefficients::high_shelf(sample_rate, frequency, q, gain_db)
+            }
+        }
+    }
+}
+
+/// A stereo filter whose settings ramp to their targets one sample at a time. The coefficients are
+/// only recomputed while that happens. Filters always run, a band at 0 dB simply passes the signal
+/// through, so moving a band away from 0 dB never starts from a stale state.
+struct Filter {
+    filter_type: FilterType,
+    target: FilterSettings,
+    frequency: Smoother<f32>,
+    q: Smoother<f32>,
+    gain_db: Smoother<f32>,
+    /// Whether the coefficients still need to follow the smoothers.
+    changing: bool,
+    /// The left and right channel filters.
+    channels: [Biquad; 2],
+}
+
+impl Filter {
+    fn new(filter_type: FilterType) -> Self {
+        Self {
+            filter_type,
+            target: FilterSettings {
+                frequency: 1000.0,
+                q: FRAC_1_SQRT_2,
+                gain_db: 0.0,
+            },
+            frequency: Smoother::new(SmoothingStyle::None),
+            q: Smoother::new(SmoothingStyle::None),
+            gain_db: Smoother::new(SmoothingStyle::None),
+            changing: false,
+            channels: [Biquad::default(); 2],
+        }
+    }
+
+    /// Jump straight to `settings`.
+    fn reset(&mut self, sample_rate: f32, settings: FilterSettings) {
+        self.target = settings;
+        self.frequency.reset(settings.frequency);
+        self.q.reset(settings.q);
+        self.gain_db.reset(settings.gain_db);
+        self.changing = false;
+
+        let coefficients = self.filter_type.coefficients(sample_rate, settings);
+        for channel in &mut self.channels {
+            channel.coefficients = coefficients;
+        }
+    }
+
+    /// Ramp to `settings` over `smoothing_ms`. Frequencies and Qs ramp logarithmically, so a move
+    /// sounds the same across the whole range.
+    fn set_target(&mut self, sample_rate: f32, smoothing_ms: f32, settings: FilterSettings) {
+        if settings == self.target {
+            return;
+        }
+        self.target = settings;
+
+        self.frequency.style = SmoothingStyle::Logarithmic(smoothing_ms);
+        self.frequency.set_target(sample_rate, settings.frequency);
+        self.q.style = SmoothingStyle::Logarithmic(smoothing_ms);
+        self.q.set_target(sample_rate, settings.q);
+        self.gain_db.style = SmoothingStyle::Linear(smoothing_ms);
+        self.gain_db.set_target(sample_rate, settings.gain_db);
+        self.changing = true;
+    }
+
+    fn process(&mut self, sample_rate: f32, left: f32, right: f32) -> (f32, f32) {
+        if self.changing {
+            let settings = FilterSettings {
+                frequency: self.frequency.next(),
+                q: self.q.next(),
+                gain_db: self.gain_db.next(),
+            };
+            let coefficients = self.filter_type.coefficients(sample_rate, settings);
+            for channel in &mut self.channels {
+                channel.coefficients = coefficients;
+            }
+            self.changing = self.frequency.is_smoothing()
+                || self.q.is_smoothing()
+                || self.gain_db.is_smoothing();
+        }
+
+        let [filter_l, filter_r] = &mut self.channels;
+        (filter_l.process(left), filter_r.process(right))
+    }
+
+    /// Clear the filter's state.
+    fn clear(&mut self) {
+        for channel in &mut self.channels {
+            channel.reset();
+        }
+    }
+}
+
+/// A stereo EQ for a single send. Changes ramp to their new values instead of switching filters in
+/// or out.
+pub struct Equalizer {
+    sample_rate: f32,
+    /// The high-pass, the low shelf, the peaking bands and the high shelf, in that order.
+    filters: [Filter; NUM_FILTERS],
+    highpass: bool,
+    /// Fades the high-pass in (1) and out (0). It keeps running while it's switched off, so
+    /// switching it back on doesn't click.
+    highpass_mix: Smoother<f32>,
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self {
+            sample_rate: 0.0,
+            filters: std::array::from_fn(|filter_idx| {
+                Filter::new(match filter_idx {
+                    0 => FilterType::Highpass,
+                    1 => FilterType::LowShelf,
+                    _ if filter_idx == NUM_FILTERS - 1 => FilterType::HighShelf,
+                    _ => FilterType::Peak,
+                })
+            }),
+            highpass: false,
+            highpass_mix: Smoother::new(SmoothingStyle::None),
+        }
+    }
+}
+
+impl Equalizer {
+    /// Jump straight to the settings without smoothing, and clear the filters' state. `value`
+    /// returns a control's plain value, with switches as 0 or 1.
+    pub fn reset(&mut self, value: impl Fn(EqControl) -> f32, sample_rate: f32) {
+        let (highpass, settings) = Self::settings(value);
+        self.sample_rate = sample_rate;
+        for (filter, settings) in self.filters.iter_mut().zip(settings) {
+            filter.reset(sample_rate, settings);
+            filter.clear();
+        }
+        self.highpass = highpass;
+        self.highpass_mix.reset(if highpass { 1.0 } else { 0.0 });
+    }
+
+    /// Ramp to the settings. Frequencies, gains and Qs move over `smoothing_ms`, switching the
+    /// high-pass on or off fades over `switch_ms`.
+    pub fn update(
+        &mut self,
+        value: impl Fn(EqControl) -> f32,
+        sample_rate: f32,
+        smoothing_ms: f32,
+        switch_ms: f32,
+    ) {
+        let (highpass, settings) = Self::settings(value);
+        self.sample_rate = sample_rate;
+        for (filter, settings) in self.filters.iter_mut().zip(settings) {
+            filter.set_target(sample_rate, smoothing_ms, settings);
+        }
+        if highpass != self.highpass {
+            self.highpass = highpass;
+            self.highpass_mix.style = SmoothingStyle::Linear(switch_ms);
+            self.highpass_mix
+                .set_target(sample_rate, if highpass { 1.0 } else { 0.0 });
+        }
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let sample_rate = self.sample_rate;
+        let [highpass, filters @ ..] = &mut self.filters;
+
+        let (highpass_l, highpass_r) = highpass.process(sample_rate, left, right);
+        let mix = self.highpass_mix.next();
+        let mut left = left + (highpass_l - left) * mix;
+        let mut right = right + (highpass_r - right) * mix;
+        for filter in filters {
+            (left, right) = filter.process(sample_rate, left, right);
+        }
+
+        (left, right)
+    }
+
+    /// Whether the high-pass is switched on, and every filter's settings.
+    fn settings(value: impl Fn(EqControl) -> f32) -> (bool, [FilterSettings; NUM_FILTERS]) {
+        let shelf = |frequency, gain_db| FilterSettings {
+            frequency: value(frequency),
+            q: FRAC_1_SQRT_2,
+            gain_db: value(gain_db),
+        };
+
+        let settings = std::array::from_fn(|filter_idx| match filter_idx {
+            0 => FilterSettings {
+                frequency: value(EqControl::HighpassFrequency),
+                q: FRAC_1_SQRT_2,
+                gain_db: 0.0,
+            },
+            1 => shelf(EqControl::LowShelfFrequency, EqControl::LowShelfGain),
+            _ if filter_idx == NUM_FILTERS - 1 => {
+                shelf(EqControl::HighShelfFrequency, EqControl::HighShelfGain)
+            }
+            _ => FilterSettings {
+                frequency: value(EqControl::PeakFrequency(filter_idx - 2)),
+                q: value(EqControl::PeakQ(filter_idx - 2)),
+                gain_db: value(EqControl::PeakGain(filter_idx - 2)),
+            },
+        });
+
+        (value(EqControl::Highpass) >= 0.5, settings)
+    }
+}
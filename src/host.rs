@@ -0,0 +1,153 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::send::{ControlParam, SendControl};
+use crate::MonitoringSenderParams;
+
+/// How often the audio thread asks for the overrides to be forwarded while there are any.
+pub const FORWARD_INTERVAL_MS: f32 = 20.0;
+
+/// Sends the values set through MIDI, remote control and scene recalls to the host as automation
+/// gestures, so the host's parameters, its automation and the saved project follow what's heard.
+/// This happens on the GUI thread whether or not the editor is open. nih-plug only hands out the
+/// context needed for that when the editor is opened though, so until the editor has been opened
+/// once after the plugin was activated the host can't be reached. The overrides are still heard
+/// and saved with the project in that case, and remote clients are told about it.
+pub struct HostSync {
+    context: RwLock<Option<Arc<dyn GuiContext>>>,
+    /// Whether `context` is set, for the audio thread and the remote servers.
+    connected: AtomicBool,
+}
+
+impl Default for HostSync {
+    fn default() -> Self {
+        Self {
+            context: RwLock::new(None),
+            connected: AtomicBool::new(false),
+        }
+    }
+}
+
+impl HostSync {
+    /// Whether changes currently reach the host's parameters.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Keep the context the editor was spawned with, it stays valid after the editor closes.
+    pub fn connect(&self, context: Arc<dyn GuiContext>) {
+        if let Ok(mut stored) = self.context.write() {
+            *stored = Some(context);
+            self.connected.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Let go of the context. The context keeps the plugin's wrapper alive, so this needs to
+    /// happen before the host unloads the plugin.
+    pub fn disconnect(&self) {
+        if let Ok(mut stored) = self.context.write() {
+            *stored = None;
+            self.connected.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Send the overrides of the first `num_sends` sends to the host, each one as a single gesture.
+    /// An override isn't sent again while the host is still picking it up. Must be called from the
+    /// GUI thread.
+    pub fn forward(&self, params: &MonitoringSenderParams, num_sends: usize) {
+        let Ok(context) = self.context.read() else {
+            return;
+        };
+        let Some(context) = context.as_deref() else {
+            return;
+        };
+        let setter = ParamSetter::new(context);
+
+        let overrides = &params.overrides;
+        for (send_index, send_params) in params.sends.iter().enumerate().take(num_sends) {
+            for control in SendControl::all() {
+                let value = match overrides.normalized(send_index, control, send_params) {
+                    Some(value) if Some(value) != overrides.forwarded(send_index, control) => value,
+                    _ => continue,
+                };
+                overrides.set_forwarded(send_index, control, value);
+
+                match control.param(send_params) {
+                    Some(ControlParam::Float(param)) => set_normalized(&setter, param, value),
+                    Some(ControlParam::Bool(param)) => set_normalized(&setter, param, value),
+                    None => (),
+                }
+            }
+        }
+    }
+
+    /// Wrap the editor so its context is kept once it's opened.
+    pub fn wrap_editor(self: &Arc<Self>, editor: Box<dyn Editor>) -> Box<dyn Editor> {
+        Box::new(ConnectingEditor {
+            editor,
+            host: self.clone(),
+        })
+    }
+}
+
+/// Set a parameter as a single gesture.
+fn set_normalized<P: Param>(setter: &ParamSetter, param: &P, normalized: f32) {
+    setter.begin_set_parameter(param);
+    setter.set_parameter_normalized(param, normalized);
+    setter.end_set_parameter(param);
+}
+
+/// Passes everything on to the actual editor, and connects the [`HostSync`] when it's spawned.
+struct ConnectingEditor {
+    editor: Box<dyn Editor>,
+    host: Arc<HostSync>,
+}
+
+impl Editor for ConnectingEditor {
+    fn spawn(
+        &self,
+        parent: ParentWindowHandle,
+        context: Arc<dyn GuiContext>,
+    ) -> Box<dyn Any + Send> {
+        self.host.connect(context.clone());
+        self.editor.spawn(parent, context)
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.editor.size()
+    }
+
+    fn set_scale_factor(&self, factor: f32) -> bool {
+        self.editor.set_scale_factor(factor)
+    }
+
+    fn param_value_changed(&self, id: &str, normalized_value: f32) {
+        self.editor.param_value_changed(id, normalized_value)
+    }
+
+    fn param_modulation_changed(&self, id: &str, modulation_offset: f32) {
+        self.editor.param_modulation_changed(id, modulation_offset)
+    }
+
+    fn param_values_changed(&self) {
+        self.editor.param_values_changed()
+    }
+}
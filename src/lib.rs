@@ -15,75 +15,342 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use nih_plug::prelude::*;
+use nih_plug_egui::EguiState;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+mod biquad;
+mod click;
+mod compressor;
+mod delay;
+mod editor;
+mod eq;
+mod host;
+mod limiter;
+mod loudness;
+pub mod meters;
+mod midi;
+mod osc;
+mod remote;
+mod scenes;
+mod send;
+mod setlist;
+mod web;
+
+use click::{ClickGenerator, ClickParams};
+use delay::{DelayDisplay, DelayUnit, FixedDelay};
+use host::HostSync;
+use loudness::LoudnessMeter;
+use meters::{LevelDetector, Meters};
+use midi::{MidiInput, MidiMapping, MidiShared};
+use osc::OscServer;
+use remote::RemoteControl;
+use scenes::{RecallScope, Scene, SceneShared, NUM_SCENES};
+use send::{SendContext, SendControl, SendParams, SendState, SharedOverrides};
+use setlist::{Setlist, SetlistEntry};
+use web::WebServer;
+
+/// The number of sends of the largest layout. There are always parameters for this many sends,
+/// layouts with fewer sends simply leave the remaining ones unused.
+const MAX_SENDS: usize = 16;
+
+/// The send counts offered to the host, one audio IO layout each. Hosts pick the first layout by
+/// default, so that's the four sends the plugin always had.
+const SEND_COUNTS: [usize; 4] = [4, 2, 8, 16];
+
+/// The IDs the four gain parameters had before the sends got parameter groups, and the IDs they
+/// have now. Projects saved with the old IDs are migrated in `filter_state()`.
+const LEGACY_GAIN_IDS: [(&str, &str); 4] = [
+    ("FOH", "gain_1"),
+    ("Axel", "gain_2"),
+    ("Sebi", "gain_3"),
+    ("Volki", "gain_4"),
+];
+
+/// The number of stereo inputs that can be mixed into every send: the main input followed by the
+/// stereo aux inputs.
+const NUM_INPUTS: usize = 4;
+
+/// The index of the mono talkback input in the aux inputs, after the matrix inputs.
+const TALKBACK_INPUT: usize = NUM_INPUTS - 1;
+
+const AUX_INPUT_PORTS: &[NonZeroU32] = &[
+    new_nonzero_u32(2),
+    new_nonzero_u32(2),
+    new_nonzero_u32(2),
+    new_nonzero_u32(1),
+];
+const AUX_INPUT_PORT_NAMES: &[&str] = &["Input 2", "Input 3", "Input 4", "Talkback"];
+
+/// The most mapped MIDI controller changes applied per block. Any more are dropped.
+const MAX_MIDI_CHANGES: usize = 1024;
+
+const SEND_PORTS: &[NonZeroU32] = &[new_nonzero_u32(2); MAX_SENDS];
+const SEND_PORT_NAMES: &[&str] = &[
+    "Send 1", "Send 2", "Send 3", "Send 4", "Send 5", "Send 6", "Send 7", "Send 8",
+    "Send 9", "Send 10", "Send 11", "Send 12", "Send 13", "Send 14", "Send 15", "Send 16",
+];
 
 pub struct MonitoringSender {
     params: std::sync::Arc<MonitoringSenderParams>,
     buffer_config: BufferConfig,
+    /// The number of sends in the layout the host picked.
+    num_sends: usize,
+    sends: [SendState; MAX_SENDS],
+    /// Whether the talk note is currently held down.
+    midi_talk: bool,
+    midi_input: MidiInput,
+    /// The scene parameter's value when it was last checked, so a recall only happens when it
+    /// changes.
+    scene_param: i32,
+    /// A scene recall that couldn't happen yet because the editor was storing a scene.
+    pending_scene: Option<usize>,
+    /// The setlist entry recalled last while the transport was playing. This is cleared when
+    /// playback stops, so starting playback always recalls the entry for that position.
+    setlist_entry: Option<SetlistEntry>,
+    click: ClickGenerator,
+    /// This block's click, preallocated for the maximum buffer size.
+    click_buffer: Vec<f32>,
+    /// The latency caused by the sends' limiters.
+    latency: u32,
+    /// The latency last reported to the host, if any.
+    reported_latency: Option<u32>,
+    /// Delays the main passthrough by the same latency as the sends.
+    main_delay: FixedDelay,
+    meters: Arc<Meters>,
+    /// The main input's left and right channel levels.
+    input_levels: [LevelDetector; 2],
+    /// Every send's left and right channel levels.
+    send_levels: [[LevelDetector; 2]; MAX_SENDS],
+    send_loudness: [LoudnessMeter; MAX_SENDS],
+    /// Changes made by remote clients, applied at the start of every block.
+    remote: Arc<RemoteControl>,
+    /// Only running if a port has been configured.
+    osc_server: Option<OscServer>,
+    /// Only running if a port has been configured.
+    web_server: Option<WebServer>,
+    /// Samples until the overrides may be forwarded to the host again.
+    samples_until_forward: usize,
 }
 
 #[derive(Params)]
 struct MonitoringSenderParams {
-    #[id = "FOH"]
-    main_gain: FloatParam,
-    #[id = "Axel"]
-    ax_gain: FloatParam,
-    #[id = "Sebi"]
-    sb_gain: FloatParam,
-    #[id = "Volki"]
-    vk_gain: FloatParam
+    /// The editor's window size.
+    #[persist = "editor-state"]
+    editor_state: Arc<EguiState>,
+
+    /// The user editable display names of the sends, saved with the project. Parameter IDs don't
+    /// depend on these, so renaming a send never breaks automation.
+    #[persist = "send-names"]
+    send_names: RwLock<Vec<String>>,
+    /// The UDP port the OSC server listens on, or `None` if it's disabled. Changes take effect the
+    /// next time the plugin is activated.
+    #[persist = "osc-port"]
+    osc_port: RwLock<Option<u16>>,
+    /// The TCP port the web mixer is served on, or `None` if it's disabled. Changes take effect the
+    /// next time the plugin is activated.
+    #[persist = "web-port"]
+    web_port: RwLock<Option<u16>>,
+    /// The MIDI controllers bound to send controls through MIDI learn.
+    #[persist = "midi-mappings"]
+    midi_mappings: RwLock<Vec<MidiMapping>>,
+    /// Shared between the audio thread and the editor for MIDI learn.
+    midi: MidiShared,
+    /// The stored scenes, [`NUM_SCENES`] slots that are `None` until a scene is stored in them.
+    #[persist = "scenes"]
+    scenes: RwLock<Vec<Option<Scene>>>,
+    /// Every send's recall safe flag and the controls scene recalls may change, indexed by send.
+    #[persist = "recall-scopes"]
+    recall_scopes: RwLock<Vec<RecallScope>>,
+    #[persist = "setlist"]
+    setlist: RwLock<Setlist>,
+    scene_state: SceneShared,
+    /// Values set through MIDI, remote control and scene recalls that the host hasn't caught up
+    /// with yet.
+    #[persist = "overrides"]
+    overrides: SharedOverrides,
+    /// Sends the overrides to the host.
+    host: Arc<HostSync>,
+
+    /// Changing this recalls the scene, with the scene's fade time.
+    #[id = "scene"]
+    scene: IntParam,
+
+    /// How long a gain change takes to ramp to its new value.
+    #[id = "smooth"]
+    smoothing_time: FloatParam,
+
+    /// The unit the sends' delay times are displayed in.
+    #[id = "delay_unit"]
+    delay_unit: EnumParam<DelayUnit>,
+    /// Shared with the delay parameters' formatters.
+    delay_display: Arc<DelayDisplay>,
+
+    #[id = "offline"]
+    offline_mode: EnumParam<OfflineMode>,
+
+    /// Momentary talkback to every send that has talkback enabled.
+    #[id = "talk"]
+    talk: BoolParam,
+    /// Holding this MIDI note also activates talkback.
+    #[id = "talk_note"]
+    talk_note: IntParam,
+    /// How much the program is dimmed on the sends receiving talkback.
+    #[id = "talk_dim"]
+    talkback_dim: FloatParam,
+
+    #[nested(group = "Click")]
+    click: ClickParams,
+
+    #[id = "main_out"]
+    main_output_mode: EnumParam<MainOutputMode>,
+    /// The send heard on the main output in [`MainOutputMode::Listen`].
+    #[id = "listen"]
+    listen_send: IntParam,
+
+    #[nested(array, group = "Send")]
+    sends: [SendParams; MAX_SENDS],
 }
 
 impl Default for MonitoringSenderParams {
     fn default() -> Self {
+        let delay_display = Arc::new(DelayDisplay::default());
+
         Self {
-            main_gain: FloatParam::new(
-                "FOH",
-                util::db_to_gain(0.00),
-                FloatRange::Skewed {
-                    min: util::db_to_gain(-144.0),
-                    max: util::db_to_gain(12.0),
-                    factor: FloatRange::gain_skew_factor(-24.0, 12.0),
+            editor_state: editor::default_state(),
+            send_names: RwLock::new(
+                SEND_PORT_NAMES.iter().map(|name| name.to_string()).collect(),
+            ),
+            osc_port: RwLock::new(None),
+            web_port: RwLock::new(None),
+            midi_mappings: RwLock::new(Vec::new()),
+            midi: MidiShared::default(),
+            scenes: RwLock::new(vec![None; NUM_SCENES]),
+            recall_scopes: RwLock::new(vec![RecallScope::default(); MAX_SENDS]),
+            setlist: RwLock::new(Setlist::default()),
+            scene_state: SceneShared::default(),
+            overrides: SharedOverrides::default(),
+            host: Arc::new(HostSync::default()),
+            scene: IntParam::new(
+                "Scene",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: NUM_SCENES as i32,
                 },
-            )
-            .with_unit(" dB")
-            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
-            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
-            ax_gain: FloatParam::new(
-                "Axel",
-                util::db_to_gain(0.00),
+            ),
+            smoothing_time: FloatParam::new(
+                "Smoothing Time",
+                20.0,
                 FloatRange::Skewed {
-                    min: util::db_to_gain(-144.0),
-                    max: util::db_to_gain(12.0),
-                    factor: FloatRange::gain_skew_factor(-24.0, 12.0),
+                    min: 0.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-2.0),
                 },
             )
-            .with_unit(" dB")
-            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
-            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
-            sb_gain: FloatParam::new(
-                "Sebi",
-                util::db_to_gain(0.00),
-                FloatRange::Skewed {
-                    min: util::db_to_gain(-144.0),
-                    max: util::db_to_gain(12.0),
-                    factor: FloatRange::gain_skew_factor(-24.0, 12.0),
+            .with_unit(" ms")
+            .with_step_size(0.1),
+            delay_unit: EnumParam::new("Delay Unit", DelayUnit::Milliseconds).with_callback({
+                let delay_display = delay_display.clone();
+                Arc::new(move |unit: DelayUnit| delay_display.set_unit(unit))
+            }),
+            offline_mode: EnumParam::new("Offline Sends", OfflineMode::Process),
+            talk: BoolParam::new("Talk", false),
+            talk_note: IntParam::new("Talk Note", 60, IntRange::Linear { min: 0, max: 127 })
+                .with_value_to_string(formatters::v2s_i32_note_formatter())
+                .with_string_to_value(formatters::s2v_i32_note_formatter()),
+            talkback_dim: FloatParam::new(
+                "Talkback Dim",
+                -20.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
                 },
             )
             .with_unit(" dB")
-            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
-            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
-            vk_gain: FloatParam::new(
-                "Volki",
-                util::db_to_gain(0.00),
-                FloatRange::Skewed {
-                    min: util::db_to_gain(-144.0),
-                    max: util::db_to_gain(12.0),
-                    factor: FloatRange::gain_skew_factor(-24.0, 12.0),
+            .with_step_size(0.1),
+            click: ClickParams::default(),
+            main_output_mode: EnumParam::new("Main Output", MainOutputMode::Passthrough),
+            listen_send: IntParam::new(
+                "Listen Send",
+                1,
+                IntRange::Linear {
+                    min: 1,
+                    max: MAX_SENDS as i32,
                 },
-            )
-            .with_unit(" dB")
-            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
-            .with_string_to_value(formatters::s2v_f32_gain_to_db()),
+            ),
+            sends: std::array::from_fn(|_| SendParams::new(delay_display.clone())),
+            delay_display,
+        }
+    }
+}
+
+/// What happens to the sends while the host renders offline.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OfflineMode {
+    #[id = "silence"]
+    #[name = "Silence Sends"]
+    Silence,
+    /// Process the sends just like in realtime.
+    #[id = "process"]
+    #[name = "Process Sends"]
+    Process,
+    /// Copy the input to every send without any processing.
+    #[id = "unity"]
+    #[name = "Input at Unity"]
+    Unity,
+    /// Silence the sends and pass the input to the main output, only delayed by the reported
+    /// latency. Nothing gets measured, so the meters are cleared.
+    #[id = "skip"]
+    #[name = "Skip"]
+    Skip,
+}
+
+/// What the main output carries.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+enum MainOutputMode {
+    /// The unprocessed input.
+    #[id = "passthrough"]
+    #[name = "Passthrough"]
+    Passthrough,
+    #[id = "silent"]
+    #[name = "Silent"]
+    Silent,
+    /// The sum of all active sends.
+    #[id = "sum"]
+    #[name = "Sum of Sends"]
+    SendSum,
+    /// A single send, so the engineer can audition a musician's mix without re-patching.
+    #[id = "listen"]
+    #[name = "Listen to Send"]
+    Listen,
+}
+
+/// Work the audio thread hands off to the GUI thread.
+pub enum Task {
+    /// Send the overrides to the host, see [`HostSync`].
+    ForwardOverrides,
+}
+
+impl MonitoringSenderParams {
+    /// The display name of the send at `index`. Falls back to the send's port name if the stored
+    /// state doesn't contain a (non-empty) name for it.
+    fn send_name(&self, index: usize) -> String {
+        self.send_names
+            .read()
+            .ok()
+            .and_then(|names| names.get(index).cloned())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| SEND_PORT_NAMES[index].to_string())
+    }
+
+    /// Rename the send at `index`. An empty name resets it to the port name.
+    fn set_send_name(&self, index: usize, name: String) {
+        if let Ok(mut names) = self.send_names.write() {
+            if names.len() <= index {
+                names.resize(index + 1, String::new());
+            }
+            names[index] = name;
         }
     }
 }
@@ -97,7 +364,27 @@ impl Default for MonitoringSender {
                 min_buffer_size: None,
                 max_buffer_size: 0,
                 process_mode: ProcessMode::Realtime,
-            }
+            },
+            num_sends: SEND_COUNTS[0],
+            sends: std::array::from_fn(|_| SendState::default()),
+            midi_talk: false,
+            midi_input: MidiInput::default(),
+            scene_param: 1,
+            pending_scene: None,
+            setlist_entry: None,
+            click: ClickGenerator::default(),
+            click_buffer: Vec::new(),
+            latency: 0,
+            reported_latency: None,
+            main_delay: FixedDelay::default(),
+            meters: Arc::new(Meters::default()),
+            input_levels: Default::default(),
+            send_levels: Default::default(),
+            send_loudness: Default::default(),
+            remote: Arc::new(RemoteControl::default()),
+            osc_server: None,
+            web_server: None,
+            samples_until_forward: 0,
         }
     }
 }
@@ -110,71 +397,173 @@ impl Plugin for MonitoringSender {
 
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
-        main_input_channels: NonZeroU32::new(2),
-        main_output_channels: NonZeroU32::new(2),
-
-        aux_input_ports: &[],
-        aux_output_ports: &[new_nonzero_u32(2); 4],
+    const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
 
-        names: PortNames {
-            layout: Some("4 stereo send channels"),
-            main_input: None,
-            // We won't output any sound here
-            main_output: Some("Same as input"),
-            aux_inputs: &[""],
-            aux_outputs: &["FOH", "Axel", "Sebi", "Volki"],
-        },
-    }];
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        stereo_sends_layout(SEND_COUNTS[0], "4 stereo send channels"),
+        stereo_sends_layout(SEND_COUNTS[1], "2 stereo send channels"),
+        stereo_sends_layout(SEND_COUNTS[2], "8 stereo send channels"),
+        stereo_sends_layout(SEND_COUNTS[3], "16 stereo send channels"),
+    ];
 
-type BackgroundTask = ();
+type BackgroundTask = Task;
 type SysExMessage = ();
 
 fn params(&self) -> std::sync::Arc<dyn Params> {
     self.params.clone()
 }
 
+fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+    let editor = editor::create(self.params.clone(), self.meters.clone())?;
+    Some(self.params.host.wrap_editor(editor))
+}
+
+fn task_executor(&mut self) -> TaskExecutor<Self> {
+    let params = self.params.clone();
+    let meters = self.meters.clone();
+    Box::new(move |task| match task {
+        Task::ForwardOverrides => {
+            params.host.forward(&params, meters.num_sends.load(Ordering::Relaxed))
+        }
+    })
+}
+
+fn filter_state(state: &mut PluginState) {
+    let mut migrated = false;
+    for (legacy_id, id) in LEGACY_GAIN_IDS {
+        if let Some(value) = state.params.remove(legacy_id) {
+            state.params.entry(String::from(id)).or_insert(value);
+            migrated = true;
+        }
+    }
+
+    // The sends used to be named after their parameters, keep those names for old projects
+    if migrated && !state.fields.contains_key("send-names") {
+        let names: Vec<&str> = LEGACY_GAIN_IDS.iter().map(|(legacy_id, _)| *legacy_id).collect();
+        if let Ok(names) = serde_json::to_string(&names) {
+            state.fields.insert(String::from("send-names"), names);
+        }
+    }
+}
+
 fn initialize(
     &mut self,
-    _layout: &AudioIOLayout,
+    layout: &AudioIOLayout,
     buffer_config: &BufferConfig,
     _context: &mut impl InitContext<Self>
 ) -> bool {
-    self.buffer_config = *buffer_config;
+    self.configure(layout.aux_output_ports.len(), buffer_config);
+    self.start_remote_servers();
+    self.reset_sends();
     true
 }
 
+fn deactivate(&mut self) {
+    self.params.host.disconnect();
+}
+
 fn reset(&mut self) {
+    self.midi_talk = false;
+    self.midi_input.reset();
+    self.setlist_entry = None;
+    self.click.reset();
+    self.main_delay.reset();
+    self.reset_sends();
+    self.reset_meters();
 }
 
 fn process(
     &mut self,
     buffer: &mut Buffer,
     aux: &mut AuxiliaryBuffers,
-    _context: &mut impl ProcessContext<Self>,
+    context: &mut impl ProcessContext<Self>,
 ) -> ProcessStatus {
-    // Don't do anything when bouncing
-    if self.buffer_config.process_mode == ProcessMode::Offline {
-        return ProcessStatus::Normal;
-    }
-    let gains = [self.params.main_gain.value(),
-                self.params.ax_gain.value(),
-                self.params.sb_gain.value(),
-                self.params.vk_gain.value()];
-    for send_index in 0..3
+    self.midi_input.clear_changes();
     {
-        let mut send_1_buffer = aux.outputs[send_index].iter_samples().into_iter();
+        let talk_note = self.params.talk_note.value();
+        // The editor only holds this lock briefly while learning, if it does the mappings are
+        // skipped for a block rather than waiting for it
+        let mappings = self.params.midi_mappings.try_read().ok();
+        let mappings: &[MidiMapping] = mappings.as_deref().map(Vec::as_slice).unwrap_or(&[]);
+        while let Some(event) = context.next_event() {
+            match event {
+                NoteEvent::NoteOn { note, .. } if note as i32 == talk_note => self.midi_talk = true,
+                NoteEvent::NoteOff { note, .. } if note as i32 == talk_note => {
+                    self.midi_talk = false
+                }
+                NoteEvent::MidiCC {
+                    timing,
+                    channel,
+                    cc,
+                    value,
+                } => self.midi_input.process_cc(
+                    timing,
+                    channel,
+                    cc,
+                    value,
+                    mappings,
+                    &self.params.midi,
+                ),
+                NoteEvent::MidiProgramChange { program, .. } if (program as usize) < NUM_SCENES => {
+                    self.pending_scene = Some(program as usize)
+                }
+                _ => (),
+            }
+        }
+    }
+    self.update_recall_safety();
+    self.recall_requested_scenes();
+    self.follow_setlist(context.transport());
 
-        for (in1, out1) in buffer.iter_samples().into_iter()
-        .map(
-            |x| { (x, send_1_buffer.next()) }
-            )
-        {
-            for (ch_in, ch_out) in in1.into_iter().zip(out1.unwrap())
-            {
-                *ch_out = *ch_in * gains[send_index];
+    if self.reported_latency != Some(self.latency) {
+        context.set_latency_samples(self.latency);
+        self.reported_latency = Some(self.latency);
+    }
+
+    let click_samples = buffer.samples().min(self.click_buffer.len());
+    self.click.render(
+        context.transport(),
+        &self.params.click,
+        &mut self.click_buffer[..click_samples],
+    );
+
+    let send_mode = match self.buffer_config.process_mode {
+        // Buffered processing isn't realtime, but it still follows the live transport
+        ProcessMode::Realtime | ProcessMode::Buffered => OfflineMode::Process,
+        ProcessMode::Offline => self.params.offline_mode.value(),
+    };
+
+    let overridden = match send_mode {
+        OfflineMode::Process => self.process_sends(buffer, &aux.inputs[..], aux.outputs, false),
+        OfflineMode::Unity => self.process_sends(buffer, &aux.inputs[..], aux.outputs, true),
+        OfflineMode::Silence | OfflineMode::Skip => {
+            for send_buffer in aux.outputs.iter_mut() {
+                silence(send_buffer);
             }
+            self.reset_meters();
+            false
         }
+    };
+
+    // The host only learns about the overrides through the GUI thread. Mapped controllers are
+    // passed on after every block they moved in, so recorded automation follows them closely.
+    self.samples_until_forward = self.samples_until_forward.saturating_sub(buffer.samples());
+    let controller_moved = !self.midi_input.changes().is_empty();
+    if overridden
+        && (self.samples_until_forward == 0 || controller_moved)
+        && self.params.host.is_connected()
+    {
+        context.execute_gui(Task::ForwardOverrides);
+        self.samples_until_forward =
+            (host::FORWARD_INTERVAL_MS / 1000.0 * self.buffer_config.sample_rate) as usize;
+    }
+
+    if send_mode == OfflineMode::Skip {
+        self.delay_main_output(buffer);
+    } else {
+        // The main buffer still contains the input at this point
+        self.measure_input(buffer);
+        self.write_main_output(buffer, &aux.outputs[..]);
     }
 
     ProcessStatus::Normal
@@ -182,7 +571,469 @@ fn process(
 }
 
 impl MonitoringSender {
+    /// The levels measured on the audio thread.
+    pub fn meters(&self) -> Arc<Meters> {
+        self.meters.clone()
+    }
+
+    /// Size everything for a layout with `num_sends` sends and this buffer config. Must not be
+    /// called from the audio thread.
+    fn configure(&mut self, num_sends: usize, buffer_config: &BufferConfig) {
+        self.buffer_config = *buffer_config;
+        self.num_sends = num_sends.min(MAX_SENDS);
+        self.meters.num_sends.store(self.num_sends, Ordering::Relaxed);
+        self.params.delay_display.set_sample_rate(buffer_config.sample_rate);
+        for send_state in self.sends.iter_mut() {
+            send_state.resize(buffer_config.sample_rate);
+        }
+        self.click.initialize(buffer_config.sample_rate);
+        self.midi_input.resize(MAX_MIDI_CHANGES);
+        // Loading a project sets the scene parameter, that shouldn't recall the scene
+        self.scene_param = self.params.scene.value();
+        self.click_buffer.resize(buffer_config.max_buffer_size as usize, 0.0);
+        let latency = limiter::latency_samples(buffer_config.sample_rate);
+        self.latency = latency as u32;
+        self.main_delay.resize(latency);
+        for level in self.input_levels.iter_mut().chain(self.send_levels.iter_mut().flatten()) {
+            level.initialize(buffer_config.sample_rate);
+        }
+        for loudness in self.send_loudness.iter_mut() {
+            loudness.resize(buffer_config.sample_rate);
+        }
+    }
+
+    /// Write this block's output to every send. Sends outside of the active layout are silenced.
+    /// With `unity` set the main input is copied to the active sends as is. Returns whether any
+    /// send has overrides the host hasn't caught up with.
+    fn process_sends(
+        &mut self,
+        buffer: &Buffer,
+        aux_inputs: &[Buffer],
+        sends: &mut [Buffer],
+        unity: bool,
+    ) -> bool {
+        let num_samples = buffer.samples();
+        let mut inputs: [(&[f32], &[f32]); NUM_INPUTS] = [(&[], &[]); NUM_INPUTS];
+        inputs[0] = stereo_channels(buffer);
+        for (input, aux_input) in inputs[1..].iter_mut().zip(aux_inputs) {
+            *input = stereo_channels(aux_input);
+        }
+        let talkback: &[f32] = aux_inputs
+            .get(TALKBACK_INPUT)
+            .and_then(|aux_input| aux_input.as_slice_immutable().first())
+            .map(|channel| &channel[..])
+            .unwrap_or(&[]);
+
+        if self.meters.take_loudness_reset() {
+            for loudness in self.send_loudness.iter_mut() {
+                loudness.reset();
+            }
+        }
+
+        let send_context = self.send_context();
+        let mut overridden = false;
+        for (send_index, send_buffer) in sends.iter_mut().enumerate() {
+            // Ports the active layout doesn't know about still shouldn't carry stale host data
+            if send_index >= self.num_sends {
+                silence(send_buffer);
+                continue;
+            }
+
+            let send_params = &self.params.sends[send_index];
+            let send_state = &mut self.sends[send_index];
+            let [level_l, level_r] = &mut self.send_levels[send_index];
+            let loudness = &mut self.send_loudness[send_index];
+            for control in SendControl::all() {
+                if let Some(value) = self.remote.take(send_index, control) {
+                    send_state.set_override(send_params, control, value);
+                }
+            }
+            send_state.update(send_params, &send_context);
+
+            let send_samples = num_samples.min(send_buffer.samples());
+            let outputs = send_buffer.as_slice();
+            let mut midi_changes = self
+                .midi_input
+                .changes()
+                .iter()
+                .filter(|change| change.send_index == send_index)
+                .peekable();
+            for sample_idx in 0..send_samples {
+                // MIDI changes take effect at the exact sample they were received at
+                while let Some(change) =
+                    midi_changes.next_if(|change| change.timing as usize <= sample_idx)
+                {
+                    send_state.set_override(send_params, change.control, change.value);
+                    send_state.update(send_params, &send_context);
+                }
+
+                let frames = inputs.map(|(input_l, input_r)| {
+                    (
+                        input_l.get(sample_idx).copied().unwrap_or(0.0),
+                        input_r.get(sample_idx).copied().unwrap_or(0.0),
+                    )
+                });
+                let (left, right) = if unity {
+                    send_state.process_unity(frames[0].0, frames[0].1)
+                } else {
+                    let talkback = talkback.get(sample_idx).copied().unwrap_or(0.0);
+                    let click = self.click_buffer.get(sample_idx).copied().unwrap_or(0.0);
+                    send_state.process(&frames, talkback, click)
+                };
+                write_frame(outputs, sample_idx, left, right);
+                level_l.process(left);
+                level_r.process(right);
+                loudness.process(left, right);
+            }
+            for channel in outputs.iter_mut() {
+                if let Some(tail) = channel.get_mut(send_samples..) {
+                    tail.fill(0.0);
+                }
+            }
+            for change in midi_changes {
+                send_state.set_override(send_params, change.control, change.value);
+                send_state.update(send_params, &send_context);
+            }
+            overridden |=
+                send_state.publish_overrides(send_params, &self.params.overrides, send_index);
+
+            let send_meters = &self.meters.sends[send_index];
+            level_l.publish(&send_meters.channels[0]);
+            level_r.publish(&send_meters.channels[1]);
+            loudness.publish(&send_meters.loudness);
+            send_meters
+                .gain_reduction_db
+                .store(send_state.gain_reduction_db(), Ordering::Relaxed);
+        }
+
+        overridden
+    }
+
+    /// Measure the main input's levels. Must be called before the main output is written.
+    fn measure_input(&mut self, buffer: &Buffer) {
+        let (left, right) = stereo_channels(buffer);
+        let [level_l, level_r] = &mut self.input_levels;
+        for (left, right) in left.iter().zip(right.iter()) {
+            level_l.process(*left);
+            level_r.process(*right);
+        }
+
+        level_l.publish(&self.meters.input[0]);
+        level_r.publish(&self.meters.input[1]);
+    }
+
+    /// Clear all level and loudness meters, for when nothing is processed.
+    fn reset_meters(&mut self) {
+        for level in self.input_levels.iter_mut().chain(self.send_levels.iter_mut().flatten()) {
+            level.reset();
+        }
+
+        for (level, meter) in self.input_levels.iter().zip(self.meters.input.iter()) {
+            level.publish(meter);
+        }
+        for loudness in self.send_loudness.iter_mut() {
+            loudness.reset();
+        }
+
+        for (levels, send_meters) in self.send_levels.iter().zip(self.meters.sends.iter()) {
+            for (level, meter) in levels.iter().zip(send_meters.channels.iter()) {
+                level.publish(meter);
+            }
+            send_meters.gain_reduction_db.store(0.0, Ordering::Relaxed);
+        }
+        for (loudness, send_meters) in self.send_loudness.iter().zip(self.meters.sends.iter()) {
+            loudness.publish(&send_meters.loudness);
+        }
+    }
+
+    /// Start, restart or stop the OSC and web servers to match the configured ports.
+    fn start_remote_servers(&mut self) {
+        let (params, meters, remote) = (&self.params, &self.meters, &self.remote);
+        update_server(
+            &mut self.osc_server,
+            &params.osc_port,
+            "OSC server",
+            OscServer::port,
+            |port| OscServer::start(port, params.clone(), meters.clone(), remote.clone()),
+        );
+        update_server(
+            &mut self.web_server,
+            &params.web_port,
+            "web server",
+            WebServer::port,
+            |port| WebServer::start(port, params.clone(), meters.clone(), remote.clone()),
+        );
+    }
+
+    /// Let the sends know whether they're recall safe. The flags keep their old values while the
+    /// editor is changing them.
+    fn update_recall_safety(&mut self) {
+        let Ok(recall_scopes) = self.params.recall_scopes.try_read() else {
+            return;
+        };
+        for (send_index, send_state) in self.sends.iter_mut().enumerate() {
+            let scope = recall_scopes.get(send_index).copied().unwrap_or_default();
+            send_state.set_recall_safe(scope.safe);
+        }
+    }
+
+    /// Recall the scenes requested by the editor, remote clients, Program Changes and the scene
+    /// parameter. The parameter only recalls its scene if that isn't the current scene already,
+    /// so the editor can both request a recall and update the parameter.
+    fn recall_requested_scenes(&mut self) {
+        if let Some(scene_index) = self.params.scene_state.take_request() {
+            self.pending_scene = Some(scene_index);
+        }
+        if let Some(scene_index) = self.pending_scene {
+            if self.recall_scene(scene_index) {
+                self.pending_scene = None;
+            }
+        }
+
+        let scene_param = self.params.scene.value();
+        if scene_param != self.scene_param {
+            self.scene_param = scene_param;
+            let scene_index = scene_param as usize - 1;
+            if self.params.scene_state.current() != Some(scene_index)
+                && !self.recall_scene(scene_index)
+            {
+                self.pending_scene = Some(scene_index);
+            }
+        }
+    }
+
+    /// Recall the setlist's scene when playback reaches a new entry, or jumps to a position
+    /// belonging to a different entry. Scenes are recalled at the start of the block.
+    fn follow_setlist(&mut self, transport: &Transport) {
+        if !transport.playing {
+            self.setlist_entry = None;
+            return;
+        }
+
+        // The editor only holds this lock while editing, the setlist is checked again on the next
+        // block
+        let active_entry = match self.params.setlist.try_read() {
+            Ok(setlist) if setlist.enabled => setlist.active_entry(transport),
+            Ok(_) => None,
+            Err(_) => return,
+        };
+        if active_entry == self.setlist_entry {
+            return;
+        }
+
+        if let Some(entry) = active_entry {
+            if !self.recall_scene(entry.scene_index) {
+                return;
+            }
+        }
+        self.setlist_entry = active_entry;
+    }
+
+    /// Fade every send to a stored scene, limited to the sends' recall scopes. Empty slots are
+    /// ignored. Returns `false` if the scenes are locked by the editor, the recall should then be
+    /// retried on the next block.
+    fn recall_scene(&mut self, scene_index: usize) -> bool {
+        let send_context = self.send_context();
+        let (Ok(scenes), Ok(recall_scopes)) = (
+            self.params.scenes.try_read(),
+            self.params.recall_scopes.try_read(),
+        ) else {
+            return false;
+        };
+        let Some(Some(scene)) = scenes.get(scene_index) else {
+            return true;
+        };
 
+        let sends = self.sends.iter_mut().zip(self.params.sends.iter());
+        for (send_index, ((send_state, send_params), values)) in
+            sends.zip(scene.sends.iter()).enumerate()
+        {
+            let scope = recall_scopes.get(send_index).copied().unwrap_or_default();
+            let values = values
+                .iter()
+                .copied()
+                .filter(|&(control, _)| scope.includes(control));
+            send_state.fade_to(send_params, values, scene.fade_ms, &send_context);
+        }
+        self.params.scene_state.set_current(scene_index);
+
+        true
+    }
+
+    /// Snap all sends to their current parameter values, or the overrides saved with the project.
+    fn reset_sends(&mut self) {
+        for (send_index, send_state) in self.sends.iter_mut().enumerate() {
+            send_state.restore_overrides(&self.params.overrides, send_index);
+        }
+        self.update_recall_safety();
+        // The saved overrides may include solos
+        let send_context = self.send_context();
+        for (send_state, send_params) in self.sends.iter_mut().zip(self.params.sends.iter()) {
+            send_state.reset(send_params, &send_context);
+        }
+    }
+
+    fn send_context(&self) -> SendContext {
+        SendContext {
+            sample_rate: self.buffer_config.sample_rate,
+            smoothing_time_ms: self.params.smoothing_time.value(),
+            any_solo: self.any_solo(SendState::solo),
+            any_manual_solo: self.any_solo(SendState::manual_solo),
+            talk: self.params.talk.value() || self.midi_talk,
+            talkback_dim_db: self.params.talkback_dim.value(),
+        }
+    }
+
+    /// Replace the input in the main buffer with whatever the main output mode asks for. `sends`
+    /// must already contain this block's send output.
+    fn write_main_output(&mut self, buffer: &mut Buffer, sends: &[Buffer]) {
+        // The input is always delayed, even if it's not heard, so the delay line never contains
+        // stale audio when switching back to passthrough
+        self.delay_main_output(buffer);
+
+        match self.params.main_output_mode.value() {
+            MainOutputMode::Passthrough => (),
+            MainOutputMode::Silent => silence(buffer),
+            MainOutputMode::SendSum => {
+                silence(buffer);
+                for send_buffer in sends.iter().take(self.num_sends) {
+                    mix_into(buffer, send_buffer);
+                }
+            }
+            MainOutputMode::Listen => {
+                silence(buffer);
+                let send_index = self.params.listen_send.value() as usize - 1;
+                if send_index < self.num_sends {
+                    if let Some(send_buffer) = sends.get(send_index) {
+                        mix_into(buffer, send_buffer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Delay the main buffer by the plugin's latency, so it stays aligned with the other tracks.
+    fn delay_main_output(&mut self, buffer: &mut Buffer) {
+        match buffer.as_slice() {
+            [] => (),
+            [mono] => {
+                for sample in mono.iter_mut() {
+                    *sample = self.main_delay.process(*sample, *sample).0;
+                }
+            }
+            [left, right, ..] => {
+                for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+                    (*left, *right) = self.main_delay.process(*left, *right);
+                }
+            }
+        }
+    }
+
+    /// Whether any of the active sends is soloed according to `soloed`. Sends outside of the
+    /// current layout can't be heard, so they can't be soloed either. A solo received through MIDI
+    /// or remote control affects the other sends from the next block on.
+    fn any_solo(&self, soloed: fn(&SendState, &SendParams) -> bool) -> bool {
+        self.params.sends[..self.num_sends]
+            .iter()
+            .zip(self.sends.iter())
+            .any(|(send_params, send_state)| soloed(send_state, send_params))
+    }
+}
+
+/// The left and right channels of an input. A mono input is used for both channels, a missing
+/// input is treated as silence.
+fn stereo_channels<'a>(buffer: &'a Buffer) -> (&'a [f32], &'a [f32]) {
+    let channels = buffer.as_slice_immutable();
+    let left: &[f32] = channels.first().map(|channel| &channel[..]).unwrap_or(&[]);
+    let right: &[f32] = channels.get(1).map(|channel| &channel[..]).unwrap_or(left);
+    (left, right)
+}
+
+/// Start, restart or stop a server so it runs on the configured port, if any.
+fn update_server<S>(
+    server: &mut Option<S>,
+    configured_port: &RwLock<Option<u16>>,
+    name: &str,
+    server_port: fn(&S) -> u16,
+    start: impl FnOnce(u16) -> std::io::Result<S>,
+) {
+    let port = configured_port.read().ok().and_then(|port| *port);
+    if server.as_ref().map(server_port) == port {
+        return;
+    }
+
+    // The old server needs to release its port first
+    *server = None;
+    if let Some(port) = port {
+        match start(port) {
+            Ok(started) => {
+                nih_log!("Started the {name} on port {port}");
+                *server = Some(started);
+            }
+            Err(err) => nih_error!("Could not start the {name} on port {port}: {err}"),
+        }
+    }
+}
+
+/// Write zeroes to every channel of `buffer`.
+fn silence(buffer: &mut Buffer) {
+    for channel in buffer.as_slice() {
+        channel.fill(0.0);
+    }
+}
+
+/// Add a send's output to `buffer`. A mono send is added to every channel.
+fn mix_into(buffer: &mut Buffer, send_buffer: &Buffer) {
+    let send = send_buffer.as_slice_immutable();
+    for (channel_idx, channel) in buffer.as_slice().iter_mut().enumerate() {
+        let send_channel = match send {
+            [mono] => mono,
+            _ => match send.get(channel_idx) {
+                Some(send_channel) => send_channel,
+                None => continue,
+            },
+        };
+        for (sample, send_sample) in channel.iter_mut().zip(send_channel.iter()) {
+            *sample += *send_sample;
+        }
+    }
+}
+
+/// Write a stereo frame to a send's channels. A mono send gets the average of both channels, and
+/// any channels past the first two are silenced.
+fn write_frame(outputs: &mut [&mut [f32]], sample_idx: usize, left: f32, right: f32) {
+    match outputs {
+        [] => (),
+        [mono] => mono[sample_idx] = (left + right) * 0.5,
+        [out_l, out_r, rest @ ..] => {
+            out_l[sample_idx] = left;
+            out_r[sample_idx] = right;
+            for channel in rest {
+                channel[sample_idx] = 0.0;
+            }
+        }
+    }
+}
+
+/// A layout with a stereo main input and output, the stereo aux inputs for the input matrix, a mono
+/// talkback input and `num_sends` stereo aux outputs.
+const fn stereo_sends_layout(num_sends: usize, name: &'static str) -> AudioIOLayout {
+    AudioIOLayout {
+        main_input_channels: NonZeroU32::new(2),
+        main_output_channels: NonZeroU32::new(2),
+
+        aux_input_ports: AUX_INPUT_PORTS,
+        aux_output_ports: SEND_PORTS.split_at(num_sends).0,
+
+        names: PortNames {
+            layout: Some(name),
+            main_input: Some("Input 1"),
+            // The input, silence or the sends depending on the main output mode
+            main_output: Some("Monitor"),
+            aux_inputs: AUX_INPUT_PORT_NAMES,
+            aux_outputs: SEND_PORT_NAMES.split_at(num_sends).0,
+        },
+    }
 }
 
 impl ClapPlugin for MonitoringSender {
@@ -207,3 +1058,217 @@ impl Vst3Plugin for MonitoringSender {
 
 nih_export_clap!(MonitoringSender);
 nih_export_vst3!(MonitoringSender);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nih_plug::wrapper::state::ParamValue;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const BLOCK_SIZE: usize = 512;
+    /// Enough blocks for the limiter's lookahead to have passed.
+    const NUM_BLOCKS: usize = 4;
+    /// What the host left in the send buffers, so stale data can be told apart from silence.
+    const STALE: f32 = 1234.0;
+
+    fn plugin(num_sends: usize) -> MonitoringSender {
+        let mut plugin = MonitoringSender::default();
+        plugin.configure(
+            num_sends,
+            &BufferConfig {
+                sample_rate: SAMPLE_RATE,
+                min_buffer_size: None,
+                max_buffer_size: BLOCK_SIZE as u32,
+                process_mode: ProcessMode::Realtime,
+            },
+        );
+        plugin.reset_sends();
+        plugin
+    }
+
+    /// Hand channels owned by the test to the plugin, like the host's buffers.
+    fn buffer(channels: &mut [Vec<f32>]) -> Buffer<'_> {
+        let num_samples = channels.first().map_or(0, Vec::len);
+        let mut buffer = Buffer::default();
+        unsafe {
+            buffer.set_slices(num_samples, |slices| {
+                *slices = channels
+                    .iter_mut()
+                    .map(|channel| channel.as_mut_slice())
+                    .collect();
+            });
+        }
+        buffer
+    }
+
+    fn sine(num_channels: usize) -> Vec<Vec<f32>> {
+        let channel: Vec<f32> = (0..BLOCK_SIZE)
+            .map(|sample_idx| (sample_idx as f32 * 0.05).sin() * 0.25)
+            .collect();
+        vec![channel; num_channels]
+    }
+
+    /// Process a few blocks with the matrix inputs and talkback present if `with_aux_inputs` is
+    /// set. `ports` has the channel lengths of every send port. Returns the last block's sends.
+    fn process(
+        plugin: &mut MonitoringSender,
+        with_aux_inputs: bool,
+        ports: &[&[usize]],
+    ) -> Vec<Vec<Vec<f32>>> {
+        let mut outputs: Vec<Vec<Vec<f32>>> = Vec::new();
+        for _ in 0..NUM_BLOCKS {
+            let mut main = sine(2);
+            let mut aux_inputs = if with_aux_inputs {
+                vec![sine(2), sine(2), sine(2), sine(1)]
+            } else {
+                Vec::new()
+            };
+            outputs = ports
+                .iter()
+                .map(|lengths| lengths.iter().map(|&length| vec![STALE; length]).collect())
+                .collect();
+
+            let mut main_buffer = buffer(&mut main);
+            let aux_input_buffers: Vec<Buffer> = aux_inputs
+                .iter_mut()
+                .map(|port| buffer(port.as_mut_slice()))
+                .collect();
+            let mut send_buffers: Vec<Buffer> = outputs
+                .iter_mut()
+                .map(|port| buffer(port.as_mut_slice()))
+                .collect();
+            plugin.process_sends(&main_buffer, &aux_input_buffers, &mut send_buffers, false);
+            plugin.write_main_output(&mut main_buffer, &send_buffers);
+        }
+
+        outputs
+    }
+
+    /// Every sample was written, and the input is heard once the limiter's lookahead has passed.
+    fn assert_written(channel: &[f32]) {
+        assert!(channel
+            .iter()
+            .all(|sample| sample.is_finite() && *sample != STALE));
+        let tail = &channel[channel.len() / 2..];
+        assert!(tail.iter().any(|sample| sample.abs() > 1e-3));
+    }
+
+    fn assert_silent(channel: &[f32]) {
+        assert!(channel.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn writes_every_port_of_every_layout() {
+        for layout in MonitoringSender::AUDIO_IO_LAYOUTS {
+            let num_sends = layout.aux_output_ports.len();
+            let mut plugin = plugin(num_sends);
+            let ports = vec![&[BLOCK_SIZE, BLOCK_SIZE][..]; num_sends];
+            let outputs = process(&mut plugin, true, &ports);
+
+            assert_eq!(outputs.len(), num_sends);
+            for channel in outputs.iter().flatten() {
+                assert_written(channel);
+            }
+        }
+    }
+
+    #[test]
+    fn silences_ports_outside_of_the_layout() {
+        let mut plugin = plugin(4);
+        let outputs = process(&mut plugin, true, &[&[BLOCK_SIZE, BLOCK_SIZE][..]; 6]);
+
+        for channel in outputs[..4].iter().flatten() {
+            assert_written(channel);
+        }
+        for channel in outputs[4..].iter().flatten() {
+            assert_silent(channel);
+        }
+    }
+
+    #[test]
+    fn handles_fewer_ports_than_the_layout() {
+        let mut plugin = plugin(8);
+        let outputs = process(&mut plugin, true, &[&[BLOCK_SIZE, BLOCK_SIZE][..]; 3]);
+        for channel in outputs.iter().flatten() {
+            assert_written(channel);
+        }
+
+        assert!(process(&mut plugin, true, &[]).is_empty());
+    }
+
+    #[test]
+    fn handles_shorter_mono_and_wider_ports() {
+        let mut plugin = plugin(4);
+        let outputs = process(
+            &mut plugin,
+            true,
+            &[
+                &[BLOCK_SIZE, BLOCK_SIZE],
+                &[BLOCK_SIZE / 2, BLOCK_SIZE / 2],
+                &[BLOCK_SIZE],
+                &[BLOCK_SIZE, BLOCK_SIZE, BLOCK_SIZE],
+            ],
+        );
+
+        for channel in outputs[..3].iter().flatten() {
+            assert_written(channel);
+        }
+        assert_eq!(outputs[1][0].len(), BLOCK_SIZE / 2);
+        assert_written(&outputs[3][0]);
+        assert_written(&outputs[3][1]);
+        assert_silent(&outputs[3][2]);
+    }
+
+    #[test]
+    fn handles_missing_aux_inputs() {
+        let mut plugin = plugin(2);
+        let outputs = process(&mut plugin, false, &[&[BLOCK_SIZE, BLOCK_SIZE][..]; 2]);
+        for channel in outputs.iter().flatten() {
+            assert_written(channel);
+        }
+    }
+
+    fn legacy_state(fields: &[(&str, &str)]) -> PluginState {
+        PluginState {
+            version: String::new(),
+            params: [("FOH", 0.5), ("Volki", 0.25)]
+                .into_iter()
+                .map(|(id, value)| (String::from(id), ParamValue::F32(value)))
+                .collect(),
+            fields: fields
+                .iter()
+                .map(|(id, value)| (String::from(*id), String::from(*value)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn migrates_legacy_gains_and_names() {
+        let mut state = legacy_state(&[]);
+        MonitoringSender::filter_state(&mut state);
+
+        assert!(
+            matches!(state.params.get("gain_1"), Some(ParamValue::F32(value)) if *value == 0.5)
+        );
+        assert!(
+            matches!(state.params.get("gain_4"), Some(ParamValue::F32(value)) if *value == 0.25)
+        );
+        assert!(!state.params.contains_key("FOH"));
+        assert_eq!(
+            state.fields.get("send-names").map(String::as_str),
+            Some(r#"["FOH","Axel","Sebi","Volki"]"#)
+        );
+    }
+
+    #[test]
+    fn keeps_stored_names_when_migrating() {
+        let names = r#"["Drums","Bass"]"#;
+        let mut state = legacy_state(&[("send-names", names)]);
+        MonitoringSender::filter_state(&mut state);
+
+        assert_eq!(
+            state.fields.get("send-names").map(String::as_str),
+            Some(names)
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use std::f32::consts::PI;
+
+use crate::delay::FixedDelay;
+
+/// How far ahead the limiter looks, and thus how long its gain reduction takes to ramp in.
+const LOOKAHEAD_MS: f32 = 1.5;
+const RELEASE_MS: f32 = 100.0;
+
+/// The true peak detector's oversampling factor.
+const OVERSAMPLING: usize = 4;
+/// The length of each polyphase branch of the interpolation filter.
+const TAPS_PER_PHASE: usize = 8;
+/// The interpolation filter's delay in samples. The audio is delayed by the same amount so the
+/// detected peaks line up with it again.
+const DETECTOR_DELAY: usize = TAPS_PER_PHASE / 2;
+
+/// The limiter's latency at this sample rate. This is the same whether the limiter is enabled or
+/// not, so toggling it doesn't shift the send in time.
+pub fn latency_samples(sample_rate: f32) -> usize {
+    lookahead_samples(sample_rate) + DETECTOR_DELAY
+}
+
+fn lookahead_samples(sample_rate: f32) -> usize {
+    (LOOKAHEAD_MS / 1000.0 * sample_rate).round().max(1.0) as usize
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimiterMode {
+    /// Only limit in-ear sends.
+    #[id = "auto"]
+    #[name = "Auto (In-Ears)"]
+    Auto,
+    #[id = "on"]
+    #[name = "On"]
+    On,
+    #[id = "off"]
+    #[name = "Off"]
+    Off,
+}
+
+/// A stereo linked lookahead brickwall limiter with oversampled true peak detection.
+pub struct Limiter {
+    detectors: [TruePeakDetector; 2],
+    /// Delays the audio by the lookahead plus the detector's delay.
+    delay: FixedDelay,
+    release_coefficient: f32,
+
+    /// A monotonic queue for the sliding minimum of the required gain over the lookahead window,
+    /// stored as a ring buffer of `(value, time)` pairs.
+    min_queue: Vec<(f32, u64)>,
+    min_head: usize,
+    min_len: usize,
+    time: u64,
+
+    /// The gain after the release stage, before it's smoothed by the moving average.
+    release_gain: f32,
+    /// The last `lookahead` release gains, averaged to ramp the gain reduction in over the
+    /// lookahead period.
+    average_buffer: Vec<f32>,
+    average_pos: usize,
+    average_sum: f64,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self {
+            detectors: Default::default(),
+            delay: FixedDelay::default(),
+            release_coefficient: 0.0,
+            min_queue: Vec::new(),
+            min_head: 0,
+            min_len: 0,
+            time: 0,
+            release_gain: 1.0,
+            average_buffer: Vec::new(),
+            average_pos: 0,
+            average_sum: 0.0,
+        }
+    }
+}
+
+impl Limiter {
+    /// Allocate the lookahead buffers for this sample rate. Must not be called from the audio
+    /// thread.
+    pub fn resize(&mut self, sample_rate: f32) {
+        let lookahead = lookahead_samples(sample_rate);
+        self.delay.resize(latency_samples(sample_rate));
+        self.min_queue.resize(lookahead + 1, (1.0, 0));
+        self.average_buffer.resize(lookahead, 1.0);
+        self.release_coefficient = (-1.0 / (RELEASE_MS / 1000.0 * sample_rate)).exp();
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        for detector in self.detectors.iter_mut() {
+            detector.reset();
+        }
+        self.delay.reset();
+        self.min_head = 0;
+        self.min_len = 0;
+        self.time = 0;
+        self.release_gain = 1.0;
+        self.average_buffer.fill(1.0);
+        self.average_pos = 0;
+        self.average_sum = self.average_buffer.len() as f64;
+    }
+
+    /// Limit a stereo frame to `ceiling`, a linear gain. The output is delayed by
+    /// [`latency_samples()`]. `amount` fades between the delayed input (0) and the limited signal
+    /// (1).
+    pub fn process(&mut self, left: f32, right: f32, ceiling: f32, amount: f32) -> (f32, f32) {
+        let peak = self.detectors[0]
+            .process(left)
+            .max(self.detectors[1].process(right));
+        let required_gain = if peak > ceiling { ceiling / peak } else { 1.0 };
+
+        // The minimum is held for the entire lookahead window, released exponentially, and then
+        // smoothed with a moving average of the same length so the gain has fully ramped down by
+        // the time the peak leaves the delay line
+        let held_gain = self.sliding_minimum(required_gain);
+        self.release_gain = if held_gain < self.release_gain {
+            held_gain
+        } else {
+            held_gain + (self.release_gain - held_gain) * self.release_coefficient
+        };
+        let gain = self.moving_average(self.release_gain);
+
+        let (left, right) = self.delay.process(left, right);
+        let gain = 1.0 + (gain - 1.0) * amount;
+        (left * gain, right * gain)
+    }
+
+    fn sliding_minimum(&mut self, value: f32) -> f32 {
+        let capacity = self.min_queue.len();
+        if capacity == 0 {
+            return value;
+        }
+
+        while self.min_len > 0 {
+            let back = (self.min_head + self.min_len - 1) % capacity;
+            if self.min_queue[back].0 >= value {
+                self.min_len -= 1;
+            } else {
+                break;
+            }
+        }
+        while self.min_len > 0 && self.min_queue[self.min_head].1 + capacity as u64 <= self.time {
+            self.min_head = (self.min_head + 1) % capacity;
+            self.min_len -= 1;
+        }
+
+        self.min_queue[(self.min_head + self.min_len) % capacity] = (value, self.time);
+        self.min_len += 1;
+        self.time += 1;
+
+        self.min_queue[self.min_head].0
+    }
+
+    fn moving_average(&mut self, value: f32) -> f32 {
+        if self.average_buffer.is_empty() {
+            return value;
+        }
+
+        self.average_sum += (value - self.average_buffer[self.average_pos]) as f64;
+        self.average_buffer[self.average_pos] = value;
+        self.average_pos = (self.average_pos + 1) % self.average_buffer.len();
+
+        (self.average_sum / self.average_buffer.len() as f64) as f32
+    }
+}
+
+/// Estimates a signal's true peak by upsampling it with a windowed sinc interpolator, similar to
+/// the method in ITU-R BS.1770.
+struct TruePeakDetector {
+    /// The polyphase branches of the interpolation filter.
+    kernel: [[f32; TAPS_PER_PHASE]; OVERSAMPLING],
+    history: [f32; TAPS_PER_PHASE],
+    pos: usize,
+}
+
+impl Default for TruePeakDetector {
+    fn default() -> Self {
+        // A Blackman windowed sinc centered on a tap, so the first branch reproduces the input
+        // samples exactly and the other branches interpolate between them
+        let length = OVERSAMPLING * TAPS_PER_PHASE;
+        let center = (length / 2) as f32;
+        let mut kernel = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+        for (phase, branch) in kernel.iter_mut().enumerate() {
+            for (tap, coefficient) in branch.iter_mut().enumerate() {
+                let k = (phase + tap * OVERSAMPLING) as f32;
+                let x = (k - center) / OVERSAMPLING as f32;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window_pos = 2.0 * PI * k / length as f32;
+                let window = 0.42 - 0.5 * window_pos.cos() + 0.08 * (2.0 * window_pos).cos();
+                *coefficient = sinc * window;
+            }
+
+            // Every branch should pass DC at unity gain
+            let sum: f32 = branch.iter().sum();
+            for coefficient in branch.iter_mut() {
+                *coefficient /= sum;
+            }
+        }
+
+        Self {
+            kernel,
+            history: [0.0; TAPS_PER_PHASE],
+            pos: 0,
+        }
+    }
+}
+
+impl TruePeakDetector {
+    fn reset(&mut self) {
+        self.history = [0.0; TAPS_PER_PHASE];
+        self.pos = 0;
+    }
+
+    /// The highest absolute value of the upsampled signal between this sample and the previous
+    /// one, delayed by [`DETECTOR_DELAY`].
+    fn process(&mut self, sample: f32) -> f32 {
+        self.pos = (self.pos + TAPS_PER_PHASE - 1) % TAPS_PER_PHASE;
+        self.history[self.pos] = sample;
+
+        let mut peak: f32 = 0.0;
+        for branch in self.kernel.iter() {
+            let mut value = 0.0;
+            for (tap, coefficient) in branch.iter().enumerate() {
+                value += self.history[(self.pos + tap) % TAPS_PER_PHASE] * coefficient;
+            }
+            peak = peak.max(value.abs());
+        }
+
+        peak
+    }
+}
@@ -0,0 +1,293 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::atomic::Ordering;
+
+use crate::biquad::{Biquad, BiquadCoefficients};
+use crate::meters::{LoudnessMeters, SILENCE_DB};
+
+/// The loudness is measured in 100 ms steps, the gating blocks overlap by 75%.
+const STEP_MS: f32 = 100.0;
+/// The 400 ms momentary window, which is also the length of a gating block.
+const MOMENTARY_STEPS: usize = 4;
+/// The 3 second short-term window.
+const SHORT_TERM_STEPS: usize = 30;
+
+/// Gating blocks quieter than this are ignored entirely.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Gating blocks more than this far below the absolute gated loudness are ignored.
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// The integrated loudness is computed from a histogram of the gating blocks instead of keeping
+/// every block, so it can run indefinitely without allocating. The bins are this wide...
+const HISTOGRAM_RESOLUTION_LU: f64 = 0.1;
+/// ...and cover everything from the absolute gate up to this loudness. Louder blocks end up in the
+/// top bin.
+const HISTOGRAM_MAX_LUFS: f64 = 10.0;
+const HISTOGRAM_BINS: usize =
+    ((HISTOGRAM_MAX_LUFS - ABSOLUTE_GATE_LUFS) / HISTOGRAM_RESOLUTION_LU) as usize;
+
+/// Measures a stereo signal's momentary, short-term and integrated loudness as specified by EBU
+/// R 128 and ITU-R BS.1770.
+#[derive(Default)]
+pub struct LoudnessMeter {
+    /// The shelf and high-pass stages of the K-weighting filter for both channels.
+    filters: [[Biquad; 2]; 2],
+    step_samples: usize,
+
+    /// The sum of the current step's squared and weighted samples over both channels.
+    step_energy: f64,
+    step_pos: usize,
+    /// The mean square of the last [`SHORT_TERM_STEPS`] steps, as a ring buffer.
+    steps: [f64; SHORT_TERM_STEPS],
+    steps_pos: usize,
+    /// The number of steps measured so far, up to [`SHORT_TERM_STEPS`].
+    steps_measured: usize,
+
+    /// The number of gating blocks in each bin, and the sum of their mean squares.
+    histogram: Vec<(u64, f64)>,
+
+    momentary: f64,
+    short_term: f64,
+    integrated: f64,
+}
+
+impl LoudnessMeter {
+    /// Design the filters and allocate the histogram for this sample rate. Must not be called from
+    /// the audio thread.
+    pub fn resize(&mut self, sample_rate: f32) {
+        let shelf = BiquadCoefficients::k_weighting_shelf(sample_rate);
+        let highpass = BiquadCoefficients::k_weighting_highpass(sample_rate);
+        for [shelf_filter, highpass_filter] in self.filters.iter_mut() {
+            shelf_filter.coefficients = shelf;
+            highpass_filter.coefficients = highpass;
+        }
+        self.step_samples = (STEP_MS / 1000.0 * sample_rate).round().max(1.0) as usize;
+        self.histogram.resize(HISTOGRAM_BINS, (0, 0.0));
+        self.reset();
+    }
+
+    /// Start a new measurement.
+    pub fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+        self.step_energy = 0.0;
+        self.step_pos = 0;
+        self.steps = [0.0; SHORT_TERM_STEPS];
+        self.steps_pos = 0;
+        self.steps_measured = 0;
+        self.histogram.fill((0, 0.0));
+        self.momentary = f64::NEG_INFINITY;
+        self.short_term = f64::NEG_INFINITY;
+        self.integrated = f64::NEG_INFINITY;
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) {
+        for ([shelf, highpass], sample) in self.filters.iter_mut().zip([left, right]) {
+            let weighted = highpass.process(shelf.process(sample)) as f64;
+            self.step_energy += weighted * weighted;
+        }
+
+        self.step_pos += 1;
+        if self.step_pos >= self.step_samples {
+            self.finish_step();
+        }
+    }
+
+    pub fn publish(&self, meters: &LoudnessMeters) {
+        meters
+            .momentary_lufs
+            .store(publishable(self.momentary), Ordering::Relaxed);
+        meters
+            .short_term_lufs
+            .store(publishable(self.short_term), Ordering::Relaxed);
+        meters
+            .integrated_lufs
+            .store(publishable(self.integrated), Ordering::Relaxed);
+    }
+
+    fn finish_step(&mut self) {
+        self.steps[self.steps_pos] = self.step_energy / self.step_samples as f64;
+        self.steps_pos = (self.steps_pos + 1) % SHORT_TERM_STEPS;
+        self.steps_measured = (self.steps_measured + 1).min(SHORT_TERM_STEPS);
+        self.step_energy = 0.0;
+        self.step_pos = 0;
+
+        if self.steps_measured >= MOMENTARY_STEPS {
+            let block = self.mean_of_last_steps(MOMENTARY_STEPS);
+            self.momentary = loudness(block);
+            self.add_gating_block(block);
+        }
+        if self.steps_measured >= SHORT_TERM_STEPS {
+            self.short_term = loudness(self.mean_of_last_steps(SHORT_TERM_STEPS));
+        }
+    }
+
+    fn mean_of_last_steps(&self, num_steps: usize) -> f64 {
+        let sum: f64 = (1..=num_steps)
+            .map(|age| self.steps[(self.steps_pos + SHORT_TERM_STEPS - age) % SHORT_TERM_STEPS])
+            .sum();
+        sum / num_steps as f64
+    }
+
+    /// Add a 400 ms block to the histogram and recompute the integrated loudness.
+    fn add_gating_block(&mut self, mean_square: f64) {
+        let block_loudness = loudness(mean_square);
+        if block_loudness < ABSOLUTE_GATE_LUFS || self.histogram.is_empty() {
+            return;
+        }
+
+        let bin_idx = histogram_bin(block_loudness).min(self.histogram.len() - 1);
+        let (count, energy) = &mut self.histogram[bin_idx];
+        *count += 1;
+        *energy += mean_square;
+
+        // The gate is rounded to the nearest bin boundary, so blocks at most half a bin below the
+        // gate are still counted and blocks at most half a bin above it are not
+        let relative_gate = loudness(self.gated_mean(0)) + RELATIVE_GATE_LU;
+        let first_bin = ((relative_gate - ABSOLUTE_GATE_LUFS) / HISTOGRAM_RESOLUTION_LU)
+            .round()
+            .max(0.0) as usize;
+        self.integrated = loudness(self.gated_mean(first_bin));
+    }
+
+    /// The mean square of all blocks in the histogram from `first_bin` up.
+    fn gated_mean(&self, first_bin: usize) -> f64 {
+        let (count, energy) = self.histogram[first_bin.min(self.histogram.len())..]
+            .iter()
+            .fold((0, 0.0), |(count, energy), (bin_count, bin_energy)| {
+                (count + bin_count, energy + bin_energy)
+            });
+        if count == 0 {
+            0.0
+        } else {
+            energy / count as f64
+        }
+    }
+}
+
+/// The loudness in LUFS of a mean square summed over all channels.
+fn loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+fn histogram_bin(loudness: f64) -> usize {
+    ((loudness - ABSOLUTE_GATE_LUFS) / HISTOGRAM_RESOLUTION_LU).max(0.0) as usize
+}
+
+/// Unmeasured and silent signals are reported the same way as silent levels.
+fn publishable(loudness: f64) -> f32 {
+    (loudness as f32).max(SILENCE_DB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+    const TOLERANCE_LU: f64 = 0.1;
+
+    /// Measure a 1 kHz sine on both channels, made of `(level in dBFS, length in seconds)`
+    /// segments, like the EBU Tech 3341 test signals.
+    fn measure(segments: &[(f32, f32)]) -> LoudnessMeter {
+        let mut meter = LoudnessMeter::default();
+        meter.resize(SAMPLE_RATE);
+
+        let phase_delta = 1000.0 / SAMPLE_RATE as f64 * std::f64::consts::TAU;
+        let mut phase = 0.0f64;
+        for &(level_dbfs, seconds) in segments {
+            let amplitude = 10.0f64.powf(level_dbfs as f64 / 20.0);
+            for _ in 0..(seconds * SAMPLE_RATE).round() as usize {
+                let sample = (phase.sin() * amplitude) as f32;
+                meter.process(sample, sample);
+                phase = (phase + phase_delta) % std::f64::consts::TAU;
+            }
+        }
+
+        meter
+    }
+
+    fn assert_loudness(measured: f64, expected: f64) {
+        assert!(
+            (measured - expected).abs() <= TOLERANCE_LU,
+            "measured {measured:.3} LUFS, expected {expected:.1} LUFS"
+        );
+    }
+
+    #[test]
+    fn sine_at_minus_23_dbfs() {
+        let meter = measure(&[(-23.0, 20.0)]);
+        assert_loudness(meter.momentary, -23.0);
+        assert_loudness(meter.short_term, -23.0);
+        assert_loudness(meter.integrated, -23.0);
+    }
+
+    #[test]
+    fn sine_at_minus_33_dbfs() {
+        let meter = measure(&[(-33.0, 20.0)]);
+        assert_loudness(meter.momentary, -33.0);
+        assert_loudness(meter.short_term, -33.0);
+        assert_loudness(meter.integrated, -33.0);
+    }
+
+    /// Tech 3341 case 3, the quiet parts are below the relative gate.
+    #[test]
+    fn relative_gate() {
+        let meter = measure(&[(-36.0, 10.0), (-23.0, 60.0), (-36.0, 10.0)]);
+        assert_loudness(meter.integrated, -23.0);
+    }
+
+    /// Tech 3341 case 4, the quietest parts are also below the absolute gate.
+    #[test]
+    fn absolute_and_relative_gate() {
+        let meter = measure(&[
+            (-72.0, 10.0),
+            (-36.0, 10.0),
+            (-23.0, 60.0),
+            (-36.0, 10.0),
+            (-72.0, 10.0),
+        ]);
+        assert_loudness(meter.integrated, -23.0);
+    }
+
+    /// Tech 3341 case 5, everything is above the gates.
+    #[test]
+    fn ungated_levels() {
+        let meter = measure(&[(-26.0, 20.0), (-20.0, 20.1), (-26.0, 20.0)]);
+        assert_loudness(meter.integrated, -23.0);
+    }
+
+    /// With equally long parts at -22.94 dBFS and a quieter level, the relative gate ends up at
+    /// about -35.72 LUFS, near the top of a histogram bin. Quiet parts 0.07 LU on either side of it
+    /// have to be gated correctly despite the histogram's 0.1 LU bins.
+    #[test]
+    fn levels_near_the_relative_gate() {
+        let meter = measure(&[(-22.94, 20.0), (-35.79, 20.0)]);
+        assert_loudness(meter.integrated, -22.96);
+
+        let meter = measure(&[(-22.94, 20.0), (-35.65, 20.0)]);
+        assert_loudness(meter.integrated, -25.72);
+    }
+
+    #[test]
+    fn reset_starts_a_new_measurement() {
+        let mut meter = measure(&[(-23.0, 10.0)]);
+        meter.reset();
+        assert_eq!(meter.integrated, f64::NEG_INFINITY);
+        assert_eq!(meter.short_term, f64::NEG_INFINITY);
+    }
+}
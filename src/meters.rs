@@ -0,0 +1,163 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use atomic_float::AtomicF32;
+use nih_plug::prelude::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::MAX_SENDS;
+
+/// How fast the peak level falls back after a peak, roughly the IEC 60268-18 return time of 20 dB
+/// in 1.7 seconds.
+const PEAK_FALL_DB_PER_SECOND: f32 = 12.0;
+/// How long the highest peak stays on the meter.
+const PEAK_HOLD_MS: f32 = 2000.0;
+/// The time constant of the RMS average.
+const RMS_WINDOW_MS: f32 = 300.0;
+
+/// The level reported for a channel that's silent or not measured.
+pub const SILENCE_DB: f32 = util::MINUS_INFINITY_DB;
+
+/// Measurements published by the audio thread. Everything in here is atomic, so the editor and
+/// remote clients can read it at any time without blocking the audio thread.
+#[derive(Default)]
+pub struct Meters {
+    /// The number of sends in the active layout. Only these sends are measured.
+    pub num_sends: AtomicUsize,
+    /// The left and right channel of the main input.
+    pub input: [LevelMeter; 2],
+    pub sends: [SendMeters; MAX_SENDS],
+
+    /// Set by [`reset_loudness()`][Self::reset_loudness()], cleared by the audio thread once it
+    /// has started a new loudness measurement.
+    loudness_reset: AtomicBool,
+}
+
+impl Meters {
+    /// Start a new loudness measurement on every send. This can be called from any thread, the
+    /// measurement is reset at the start of the next block.
+    pub fn reset_loudness(&self) {
+        self.loudness_reset.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the loudness measurement should be reset. Only the audio thread should call this.
+    pub(crate) fn take_loudness_reset(&self) -> bool {
+        self.loudness_reset.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+pub struct SendMeters {
+    /// The left and right channel of the send's output.
+    pub channels: [LevelMeter; 2],
+    /// The compressor's current gain reduction in decibels, as a negative number.
+    pub gain_reduction_db: AtomicF32,
+    pub loudness: LoudnessMeters,
+}
+
+/// A single channel's levels in decibels.
+pub struct LevelMeter {
+    pub peak_db: AtomicF32,
+    /// The highest peak of the last [`PEAK_HOLD_MS`] milliseconds.
+    pub peak_hold_db: AtomicF32,
+    pub rms_db: AtomicF32,
+}
+
+impl Default for LevelMeter {
+    fn default() -> Self {
+        Self {
+            peak_db: AtomicF32::new(SILENCE_DB),
+            peak_hold_db: AtomicF32::new(SILENCE_DB),
+            rms_db: AtomicF32::new(SILENCE_DB),
+        }
+    }
+}
+
+/// A send's loudness in LUFS, published by the audio thread.
+pub struct LoudnessMeters {
+    pub momentary_lufs: AtomicF32,
+    pub short_term_lufs: AtomicF32,
+    pub integrated_lufs: AtomicF32,
+}
+
+impl Default for LoudnessMeters {
+    fn default() -> Self {
+        Self {
+            momentary_lufs: AtomicF32::new(SILENCE_DB),
+            short_term_lufs: AtomicF32::new(SILENCE_DB),
+            integrated_lufs: AtomicF32::new(SILENCE_DB),
+        }
+    }
+}
+
+/// Measures a single channel's levels on the audio thread. The results are written to a
+/// [`LevelMeter`] once per block.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LevelDetector {
+    peak_fall_coefficient: f32,
+    rms_coefficient: f32,
+    hold_samples: usize,
+
+    peak: f32,
+    hold: f32,
+    hold_remaining: usize,
+    mean_square: f32,
+}
+
+impl LevelDetector {
+    pub fn initialize(&mut self, sample_rate: f32) {
+        self.peak_fall_coefficient = util::db_to_gain(-PEAK_FALL_DB_PER_SECOND / sample_rate);
+        self.rms_coefficient = (-1.0 / (RMS_WINDOW_MS / 1000.0 * sample_rate)).exp();
+        self.hold_samples = (PEAK_HOLD_MS / 1000.0 * sample_rate).round() as usize;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.peak = 0.0;
+        self.hold = 0.0;
+        self.hold_remaining = 0;
+        self.mean_square = 0.0;
+    }
+
+    pub fn process(&mut self, sample: f32) {
+        let level = sample.abs();
+        self.peak = level.max(self.peak * self.peak_fall_coefficient);
+
+        if self.peak >= self.hold {
+            self.hold = self.peak;
+            self.hold_remaining = self.hold_samples;
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+        } else {
+            self.hold = self.peak;
+        }
+
+        let square = sample * sample;
+        self.mean_square = square + (self.mean_square - square) * self.rms_coefficient;
+    }
+
+    pub fn publish(&self, meter: &LevelMeter) {
+        meter
+            .peak_db
+            .store(util::gain_to_db(self.peak), Ordering::Relaxed);
+        meter
+            .peak_hold_db
+            .store(util::gain_to_db(self.hold), Ordering::Relaxed);
+        meter
+            .rms_db
+            .store(util::gain_to_db(self.mean_square.sqrt()), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,252 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::send::SendControl;
+use crate::MAX_SENDS;
+
+/// The controller numbers used to select and set NRPNs.
+const CC_DATA_ENTRY_MSB: u8 = 6;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_NRPN_LSB: u8 = 98;
+const CC_NRPN_MSB: u8 = 99;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+
+/// The highest 14-bit value.
+const MAX_14_BIT: f32 = 16383.0;
+
+/// Where a MIDI controller's value comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MidiSource {
+    /// A 7-bit control change.
+    Cc { channel: u8, cc: u8 },
+    /// A 14-bit non-registered parameter number, set through the data entry controllers.
+    Nrpn { channel: u8, parameter: u16 },
+}
+
+impl MidiSource {
+    /// Packs the source into a single number, so it can be shared through an atomic.
+    fn to_bits(self) -> u32 {
+        match self {
+            MidiSource::Cc { channel, cc } => ((channel as u32) << 16) | cc as u32,
+            MidiSource::Nrpn { channel, parameter } => {
+                (1 << 24) | ((channel as u32) << 16) | parameter as u32
+            }
+        }
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        let channel = (bits >> 16) as u8;
+        if bits >> 24 == 0 {
+            MidiSource::Cc {
+                channel,
+                cc: bits as u8,
+            }
+        } else {
+            MidiSource::Nrpn {
+                channel,
+                parameter: bits as u16,
+            }
+        }
+    }
+
+    /// A short description like `Ch 1 CC 7`, with channels numbered from 1.
+    pub fn describe(self) -> String {
+        match self {
+            MidiSource::Cc { channel, cc } => format!("Ch {} CC {cc}", channel + 1),
+            MidiSource::Nrpn { channel, parameter } => {
+                format!("Ch {} NRPN {parameter}", channel + 1)
+            }
+        }
+    }
+}
+
+/// A learned binding from a MIDI controller to a send's control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    pub source: MidiSource,
+    pub send_index: usize,
+    pub control: SendControl,
+}
+
+/// A mapped controller's value, applied to a send at `timing` within the block.
+#[derive(Debug, Clone, Copy)]
+pub struct MidiChange {
+    pub timing: u32,
+    pub send_index: usize,
+    pub control: SendControl,
+    /// The control's normalized value.
+    pub value: f32,
+}
+
+/// The state shared between the audio thread and the editor. The audio thread reports the
+/// controllers it receives so the editor can learn them.
+#[derive(Default)]
+pub struct MidiShared {
+    /// The last controller received, see [`MidiSource::to_bits()`].
+    last_source: AtomicU32,
+    /// Incremented every time a controller is received, so the editor can tell when to learn.
+    received: AtomicU32,
+}
+
+impl MidiShared {
+    /// How many controllers have been received so far, and the last one of them.
+    pub fn last_received(&self) -> (u32, MidiSource) {
+        let received = self.received.load(Ordering::Acquire);
+        let source = MidiSource::from_bits(self.last_source.load(Ordering::Relaxed));
+        (received, source)
+    }
+
+    fn set_received(&self, source: MidiSource) {
+        self.last_source.store(source.to_bits(), Ordering::Relaxed);
+        self.received.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// The NRPN selection and data entry state of a single MIDI channel.
+#[derive(Debug, Default, Clone, Copy)]
+struct NrpnState {
+    parameter_msb: Option<u8>,
+    parameter_lsb: Option<u8>,
+    data_msb: u8,
+}
+
+impl NrpnState {
+    fn parameter(&self) -> Option<u16> {
+        Some(((self.parameter_msb? as u16) << 7) | self.parameter_lsb? as u16)
+    }
+}
+
+/// Turns incoming control changes into values for the mapped send controls on the audio thread.
+/// The sends apply them as overrides at the sample they were received at, and the overrides are
+/// forwarded to the host's parameters from there.
+pub struct MidiInput {
+    nrpn: [NrpnState; 16],
+    /// This block's changes, preallocated so they can be collected without allocating.
+    changes: Vec<MidiChange>,
+}
+
+impl Default for MidiInput {
+    fn default() -> Self {
+        Self {
+            nrpn: [NrpnState::default(); 16],
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl MidiInput {
+    /// Allocate room for this many changes per block. Must not be called from the audio thread.
+    pub fn resize(&mut self, capacity: usize) {
+        self.changes
+            .reserve(capacity.saturating_sub(self.changes.len()));
+    }
+
+    pub fn reset(&mut self) {
+        self.nrpn = [NrpnState::default(); 16];
+        self.changes.clear();
+    }
+
+    /// Forget the last block's changes. Called at the start of every block.
+    pub fn clear_changes(&mut self) {
+        self.changes.clear();
+    }
+
+    /// This block's changes, in the order they were received.
+    pub fn changes(&self) -> &[MidiChange] {
+        &self.changes
+    }
+
+    /// Handle a control change with a normalized `value`, as nih-plug reports it. Mapped values are
+    /// added to this block's changes.
+    pub fn process_cc(
+        &mut self,
+        timing: u32,
+        channel: u8,
+        cc: u8,
+        value: f32,
+        mappings: &[MidiMapping],
+        shared: &MidiShared,
+    ) {
+        let data = (value * 127.0).round() as u8;
+        let Some(nrpn) = self.nrpn.get_mut(channel as usize) else {
+            return;
+        };
+
+        let (source, value) = match cc {
+            CC_NRPN_MSB => {
+                nrpn.parameter_msb = Some(data);
+                return;
+            }
+            CC_NRPN_LSB => {
+                nrpn.parameter_lsb = Some(data);
+                return;
+            }
+            // Selecting an RPN deselects the NRPN, the data entry then belongs to the RPN
+            CC_RPN_MSB | CC_RPN_LSB => {
+                *nrpn = NrpnState::default();
+                return;
+            }
+            // Controllers often only send the coarse value, so it's applied right away and then
+            // refined once the fine value arrives
+            CC_DATA_ENTRY_MSB => {
+                let Some(parameter) = nrpn.parameter() else {
+                    return;
+                };
+                nrpn.data_msb = data;
+                (
+                    MidiSource::Nrpn { channel, parameter },
+                    ((data as u16) << 7) as f32 / MAX_14_BIT,
+                )
+            }
+            CC_DATA_ENTRY_LSB => {
+                let Some(parameter) = nrpn.parameter() else {
+                    return;
+                };
+                (
+                    MidiSource::Nrpn { channel, parameter },
+                    (((nrpn.data_msb as u16) << 7) | data as u16) as f32 / MAX_14_BIT,
+                )
+            }
+            _ => (MidiSource::Cc { channel, cc }, value),
+        };
+
+        shared.set_received(source);
+        let mapped = mappings
+            .iter()
+            .filter(|mapping| mapping.source == source && mapping.send_index < MAX_SENDS);
+        for mapping in mapped {
+            // Anything past the preallocated capacity is dropped rather than allocating
+            if self.changes.len() < self.changes.capacity() {
+                self.changes.push(MidiChange {
+                    timing,
+                    send_index: mapping.send_index,
+                    control: mapping.control,
+                    value,
+                });
+            }
+        }
+    }
+}
+
+/// Bind `source` to a send's control, replacing anything else that source was bound to.
+pub fn learn(mappings: &mut Vec<MidiMapping>, mapping: MidiMapping) {
+    mappings.retain(|existing| existing.source != mapping.source);
+    mappings.push(mapping);
+}
@@ -0,0 +1,394 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::meters::Meters;
+use crate::remote::RemoteControl;
+use crate::scenes::NUM_SCENES;
+use crate::send::SendControl;
+use crate::MonitoringSenderParams;
+
+/// The port suggested when the OSC server gets enabled.
+pub const DEFAULT_PORT: u16 = 9000;
+/// How often subscribed clients get sent the current values and meters.
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(50);
+/// Clients have to renew their subscription within this time, so clients that went away don't
+/// get sent feedback forever.
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// The most clients that can subscribe at the same time.
+const MAX_SUBSCRIBERS: usize = 32;
+
+/// An OSC server on its own thread. Changes are passed to the audio thread through
+/// [`RemoteControl`]. The server understands these messages, with sends numbered from 1:
+///
+/// - `/send/{n}/gain`, `/send/{n}/pan` and `/send/{n}/width` with the parameter's normalized value
+///   as a float between 0 and 1.
+/// - `/send/{n}/mute` and `/send/{n}/solo` with an int, float or bool, anything but zero enables
+///   them.
+/// - `/scene` with the number of the scene to recall.
+/// - `/subscribe` and `/unsubscribe` to start and stop receiving feedback on the port the message
+///   was sent from.
+///
+/// Subscribers are sent a bundle for every active send every [`FEEDBACK_INTERVAL`], containing the
+/// same addresses with their current values plus `/send/{n}/name`, `/send/{n}/peak` and
+/// `/send/{n}/lufs` with the peak level in dBFS and the short-term loudness. Every bundle also has
+/// `/host` set to 1 if changes reach the host's parameters, or 0 if they're only heard and saved
+/// with the project until the plugin's window has been opened (see
+/// [`HostSync`][crate::host::HostSync]).
+pub struct OscServer {
+    port: u16,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscServer {
+    /// Bind to `port` on all interfaces and start the server thread.
+    pub fn start(
+        port: u16,
+        params: Arc<MonitoringSenderParams>,
+        meters: Arc<Meters>,
+        remote: Arc<RemoteControl>,
+    ) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        // Port 0 picks any free port
+        let port = socket.local_addr()?.port();
+        // The timeout makes sure feedback is sent and the thread notices when it should stop
+        socket.set_read_timeout(Some(FEEDBACK_INTERVAL / 2))?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = std::thread::Builder::new()
+            .name(String::from("OSC server"))
+            .spawn({
+                let running = running.clone();
+                move || {
+                    Connection {
+                        socket,
+                        params,
+                        meters,
+                        remote,
+                        subscribers: Vec::new(),
+                    }
+                    .run(&running)
+                }
+            })?;
+
+        Ok(Self {
+            port,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The server thread's state.
+struct Connection {
+    socket: UdpSocket,
+    params: Arc<MonitoringSenderParams>,
+    meters: Arc<Meters>,
+    remote: Arc<RemoteControl>,
+    /// Clients receiving feedback, and when they last subscribed.
+    subscribers: Vec<(SocketAddr, Instant)>,
+}
+
+impl Connection {
+    fn run(&mut self, running: &AtomicBool) {
+        let mut buffer = [0u8; rosc::decoder::MTU];
+        let mut last_feedback = Instant::now();
+
+        while running.load(Ordering::Relaxed) {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, from)) => match rosc::decoder::decode_udp(&buffer[..size]) {
+                    Ok((_, packet)) => self.handle_packet(packet, from),
+                    Err(err) => nih_log!("Ignoring an invalid OSC packet from {from}: {err:?}"),
+                },
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(err) => {
+                    nih_error!("OSC server stopped: {err}");
+                    return;
+                }
+            }
+
+            if last_feedback.elapsed() >= FEEDBACK_INTERVAL {
+                last_feedback = Instant::now();
+                self.send_feedback();
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: OscPacket, from: SocketAddr) {
+        match packet {
+            OscPacket::Message(message) => self.handle_message(message, from),
+            OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    self.handle_packet(packet, from);
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: OscMessage, from: SocketAddr) {
+        let path: Vec<&str> = message.addr.trim_start_matches('/').split('/').collect();
+        match path[..] {
+            ["subscribe"] => self.subscribe(from),
+            ["unsubscribe"] => self.subscribers.retain(|(addr, _)| *addr != from),
+            ["scene"] => {
+                let scene_number = match message.args.first().and_then(float_arg) {
+                    Some(value) if (1.0..=NUM_SCENES as f32).contains(&value.round()) => {
+                        value.round() as usize
+                    }
+                    _ => return,
+                };
+                self.params.scene_state.request_recall(scene_number - 1);
+            }
+            ["send", send_number, control] => {
+                let num_sends = self.meters.num_sends.load(Ordering::Relaxed);
+                let send_index = match send_number.parse::<usize>() {
+                    Ok(send_number) if (1..=num_sends).contains(&send_number) => send_number - 1,
+                    _ => return,
+                };
+                let Some(value) = message.args.first().and_then(float_arg) else {
+                    return;
+                };
+
+                let (control, value) = match control {
+                    "gain" => (SendControl::Gain, value),
+                    "pan" => (SendControl::Pan, value),
+                    "width" => (SendControl::Width, value),
+                    "mute" => (SendControl::Mute, (value != 0.0) as u8 as f32),
+                    "solo" => (SendControl::Solo, (value != 0.0) as u8 as f32),
+                    _ => return,
+                };
+                self.remote.set(send_index, control, value);
+            }
+            _ => (),
+        }
+    }
+
+    fn subscribe(&mut self, from: SocketAddr) {
+        let now = Instant::now();
+        if let Some((_, subscribed)) = self.subscribers.iter_mut().find(|(addr, _)| *addr == from) {
+            *subscribed = now;
+        } else if self.subscribers.len() < MAX_SUBSCRIBERS {
+            self.subscribers.push((from, now));
+        }
+    }
+
+    fn send_feedback(&mut self) {
+        self.subscribers
+            .retain(|(_, subscribed)| subscribed.elapsed() < SUBSCRIPTION_TIMEOUT);
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let host = OscPacket::Message(OscMessage {
+            addr: String::from("/host"),
+            args: vec![OscType::Int(self.params.host.is_connected() as i32)],
+        });
+        let num_sends = self.meters.num_sends.load(Ordering::Relaxed);
+        for send_index in 0..num_sends {
+            let mut content = self.send_feedback_messages(send_index);
+            content.push(host.clone());
+            let packet = OscPacket::Bundle(OscBundle {
+                timetag: OscTime::from((0, 1)),
+                content,
+            });
+            let Ok(bytes) = rosc::encoder::encode(&packet) else {
+                continue;
+            };
+
+            for (addr, _) in &self.subscribers {
+                // Clients that can't be reached simply time out
+                let _ = self.socket.send_to(&bytes, addr);
+            }
+        }
+    }
+
+    fn send_feedback_messages(&self, send_index: usize) -> Vec<OscPacket> {
+        let send_params = &self.params.sends[send_index];
+        let send_meters = &self.meters.sends[send_index];
+        let peak_db = send_meters
+            .channels
+            .iter()
+            .map(|channel| channel.peak_db.load(Ordering::Relaxed))
+            .fold(f32::NEG_INFINITY, f32::max);
+        let prefix = format!("/send/{}", send_index + 1);
+        // Values set through MIDI or scene recalls may not have reached the parameters yet
+        let value = |control| {
+            self.params
+                .overrides
+                .effective_normalized(send_index, control, send_params)
+        };
+
+        [
+            ("name", OscType::String(self.params.send_name(send_index))),
+            ("gain", OscType::Float(value(SendControl::Gain))),
+            ("pan", OscType::Float(value(SendControl::Pan))),
+            ("width", OscType::Float(value(SendControl::Width))),
+            (
+                "mute",
+                OscType::Int((value(SendControl::Mute) >= 0.5) as i32),
+            ),
+            (
+                "solo",
+                OscType::Int((value(SendControl::Solo) >= 0.5) as i32),
+            ),
+            ("peak", OscType::Float(peak_db)),
+            (
+                "lufs",
+                OscType::Float(send_meters.loudness.short_term_lufs.load(Ordering::Relaxed)),
+            ),
+        ]
+        .into_iter()
+        .map(|(control, value)| {
+            OscPacket::Message(OscMessage {
+                addr: format!("{prefix}/{control}"),
+                args: vec![value],
+            })
+        })
+        .collect()
+    }
+}
+
+/// Most OSC controllers send floats, but ints and bools are accepted for the switches.
+fn float_arg(arg: &OscType) -> Option<f32> {
+    match *arg {
+        OscType::Float(value) => Some(value),
+        OscType::Double(value) => Some(value as f32),
+        OscType::Int(value) => Some(value as f32),
+        OscType::Bool(value) => Some(if value { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn start_server() -> (OscServer, Arc<RemoteControl>, UdpSocket) {
+        let meters = Arc::new(Meters::default());
+        meters.num_sends.store(4, Ordering::Relaxed);
+        let remote = Arc::new(RemoteControl::default());
+        let server = OscServer::start(
+            0,
+            Arc::new(MonitoringSenderParams::default()),
+            meters,
+            remote.clone(),
+        )
+        .unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        client.connect(("127.0.0.1", server.port())).unwrap();
+
+        (server, remote, client)
+    }
+
+    fn send(client: &UdpSocket, addr: &str, args: Vec<OscType>) {
+        let packet = OscPacket::Message(OscMessage {
+            addr: addr.to_string(),
+            args,
+        });
+        client
+            .send(&rosc::encoder::encode(&packet).unwrap())
+            .unwrap();
+    }
+
+    /// Wait for the server thread to pass on a change.
+    fn wait_for_change(remote: &RemoteControl, send_index: usize, control: SendControl) -> f32 {
+        let started = Instant::now();
+        loop {
+            if let Some(value) = remote.take(send_index, control) {
+                return value;
+            }
+            assert!(started.elapsed() < TIMEOUT, "no change for {control:?}");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn changes_from_a_loopback_client() {
+        let (_server, remote, client) = start_server();
+
+        send(&client, "/send/2/gain", vec![OscType::Float(0.25)]);
+        assert_eq!(wait_for_change(&remote, 1, SendControl::Gain), 0.25);
+
+        send(&client, "/send/1/mute", vec![OscType::Int(1)]);
+        assert_eq!(wait_for_change(&remote, 0, SendControl::Mute), 1.0);
+
+        send(&client, "/send/3/pan", vec![OscType::Float(2.0)]);
+        assert_eq!(wait_for_change(&remote, 2, SendControl::Pan), 1.0);
+    }
+
+    #[test]
+    fn ignores_sends_outside_of_the_layout() {
+        let (_server, remote, client) = start_server();
+
+        send(&client, "/send/5/gain", vec![OscType::Float(0.25)]);
+        send(&client, "/send/1/gain", vec![OscType::Float(0.5)]);
+        assert_eq!(wait_for_change(&remote, 0, SendControl::Gain), 0.5);
+        assert_eq!(remote.take(4, SendControl::Gain), None);
+    }
+
+    #[test]
+    fn feedback_for_subscribers() {
+        let (_server, _remote, client) = start_server();
+        send(&client, "/subscribe", Vec::new());
+
+        let mut buffer = [0u8; rosc::decoder::MTU];
+        let size = client.recv(&mut buffer).unwrap();
+        let (_, packet) = rosc::decoder::decode_udp(&buffer[..size]).unwrap();
+        let OscPacket::Bundle(bundle) = packet else {
+            panic!("expected a bundle, got {packet:?}");
+        };
+        let addresses: Vec<String> = bundle
+            .content
+            .into_iter()
+            .filter_map(|packet| match packet {
+                OscPacket::Message(message) => Some(message.addr),
+                OscPacket::Bundle(_) => None,
+            })
+            .collect();
+        assert!(addresses.iter().any(|addr| addr.ends_with("/gain")));
+        assert!(addresses.iter().any(|addr| addr.ends_with("/lufs")));
+        assert!(addresses.iter().any(|addr| addr == "/host"));
+    }
+}
@@ -0,0 +1,59 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use atomic_float::AtomicF32;
+use std::sync::atomic::Ordering;
+
+use crate::send::{SendControl, NUM_SEND_CONTROLS};
+use crate::MAX_SENDS;
+
+/// Changes made by remote control clients, waiting to be picked up by the audio thread. The audio
+/// thread applies them as overrides just like mapped MIDI controllers, so they're heard right away,
+/// and [`HostSync`][crate::host::HostSync] passes them on to the host as automation gestures. Only
+/// the latest value for every control is kept.
+pub struct RemoteControl {
+    /// Normalized values, NaN for controls that haven't changed.
+    changes: [[AtomicF32; NUM_SEND_CONTROLS]; MAX_SENDS],
+}
+
+impl Default for RemoteControl {
+    fn default() -> Self {
+        Self {
+            changes: std::array::from_fn(|_| std::array::from_fn(|_| AtomicF32::new(f32::NAN))),
+        }
+    }
+}
+
+impl RemoteControl {
+    /// Set a control to a normalized value at the start of the next block. This can be called from
+    /// any thread.
+    pub fn set(&self, send_index: usize, control: SendControl, normalized: f32) {
+        let Some(change) = control
+            .index()
+            .and_then(|control_index| self.changes.get(send_index)?.get(control_index))
+        else {
+            return;
+        };
+        change.store(normalized.clamp(0.0, 1.0), Ordering::Relaxed);
+    }
+
+    /// The control's latest change, if any. Only the audio thread should call this.
+    pub(crate) fn take(&self, send_index: usize, control: SendControl) -> Option<f32> {
+        let change = self.changes.get(send_index)?.get(control.index()?)?;
+        let normalized = change.swap(f32::NAN, Ordering::Relaxed);
+        (!normalized.is_nan()).then_some(normalized)
+    }
+}
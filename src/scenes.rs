@@ -0,0 +1,209 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::send::{SendControl, SendParams, SharedOverrides};
+
+/// The number of scene slots.
+pub const NUM_SCENES: usize = 64;
+/// The fade time new scenes are stored with.
+pub const DEFAULT_FADE_MS: f32 = 2000.0;
+/// The longest fade a scene can have.
+pub const MAX_FADE_MS: f32 = 60_000.0;
+
+/// A snapshot of every send's mix.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+    /// How long recalling the scene takes to morph from the current values to the scene's.
+    pub fade_ms: f32,
+    /// Every send's normalized control values, indexed by send.
+    pub sends: Vec<Vec<(SendControl, f32)>>,
+}
+
+impl Scene {
+    /// Capture the values the sends currently have. Controls that were overridden through MIDI,
+    /// remote control or an earlier recall are stored with the overridden values, since those are
+    /// what's heard, even if the host hasn't caught up with them yet.
+    pub fn capture(
+        name: String,
+        fade_ms: f32,
+        sends: &[SendParams],
+        overrides: &SharedOverrides,
+    ) -> Self {
+        let sends = sends
+            .iter()
+            .enumerate()
+            .map(|(send_index, send_params)| {
+                SendControl::all()
+                    .filter(|control| control.param(send_params).is_some())
+                    .map(|control| {
+                        let value =
+                            overrides.effective_normalized(send_index, control, send_params);
+                        (control, value)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            name,
+            fade_ms,
+            sends,
+        }
+    }
+}
+
+/// What scene recalls may change on a send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecallScope {
+    /// Recall safe sends are never changed by scene recalls, and they aren't silenced when a
+    /// recall solos another send.
+    pub safe: bool,
+    /// The [`ScopeGroup`]s recalls may change, one bit per group.
+    groups: u8,
+}
+
+impl Default for RecallScope {
+    fn default() -> Self {
+        Self {
+            safe: false,
+            groups: u8::MAX,
+        }
+    }
+}
+
+impl RecallScope {
+    /// Whether a scene recall may change a control.
+    pub fn includes(&self, control: SendControl) -> bool {
+        !self.safe && self.contains(ScopeGroup::of(control))
+    }
+
+    pub fn contains(&self, group: ScopeGroup) -> bool {
+        self.groups & group.bit() != 0
+    }
+
+    pub fn set(&mut self, group: ScopeGroup, included: bool) {
+        if included {
+            self.groups |= group.bit();
+        } else {
+            self.groups &= !group.bit();
+        }
+    }
+}
+
+/// The groups of send controls a send's [`RecallScope`] can include.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeGroup {
+    Gain,
+    /// Mute and solo.
+    Mute,
+    /// Pan, width and mono.
+    Pan,
+    /// The input levels.
+    Inputs,
+    Delay,
+    /// The talkback and click levels.
+    Cues,
+    /// All of the EQ's settings.
+    Eq,
+}
+
+impl ScopeGroup {
+    pub fn all() -> [ScopeGroup; 7] {
+        [
+            ScopeGroup::Gain,
+            ScopeGroup::Mute,
+            ScopeGroup::Pan,
+            ScopeGroup::Inputs,
+            ScopeGroup::Delay,
+            ScopeGroup::Cues,
+            ScopeGroup::Eq,
+        ]
+    }
+
+    /// The group a control belongs to.
+    pub fn of(control: SendControl) -> Self {
+        match control {
+            SendControl::Gain => ScopeGroup::Gain,
+            SendControl::Mute | SendControl::Solo => ScopeGroup::Mute,
+            SendControl::Pan | SendControl::Width | SendControl::Mono => ScopeGroup::Pan,
+            SendControl::InputLevel(_) => ScopeGroup::Inputs,
+            SendControl::Delay => ScopeGroup::Delay,
+            SendControl::TalkbackLevel | SendControl::ClickLevel => ScopeGroup::Cues,
+            SendControl::Eq(_) => ScopeGroup::Eq,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScopeGroup::Gain => "Gain",
+            ScopeGroup::Mute => "Mute & Solo",
+            ScopeGroup::Pan => "Pan & Width",
+            ScopeGroup::Inputs => "Input Levels",
+            ScopeGroup::Delay => "Delay",
+            ScopeGroup::Cues => "Talkback & Click",
+            ScopeGroup::Eq => "EQ",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Scene recalls requested by the editor or remote clients, and the last scene recalled by the
+/// audio thread.
+pub struct SceneShared {
+    /// The index of the scene to recall at the start of the next block, or -1.
+    requested: AtomicI32,
+    /// The index of the last recalled scene, or -1 if no scene has been recalled yet.
+    current: AtomicI32,
+}
+
+impl Default for SceneShared {
+    fn default() -> Self {
+        Self {
+            requested: AtomicI32::new(-1),
+            current: AtomicI32::new(-1),
+        }
+    }
+}
+
+impl SceneShared {
+    /// Recall a scene at the start of the next block. This can be called from any thread.
+    pub fn request_recall(&self, scene_index: usize) {
+        if scene_index < NUM_SCENES {
+            self.requested.store(scene_index as i32, Ordering::Relaxed);
+        }
+    }
+
+    /// The last recalled scene, if any.
+    pub fn current(&self) -> Option<usize> {
+        usize::try_from(self.current.load(Ordering::Relaxed)).ok()
+    }
+
+    /// The requested recall, if any. Only the audio thread should call this.
+    pub(crate) fn take_request(&self) -> Option<usize> {
+        usize::try_from(self.requested.swap(-1, Ordering::Relaxed)).ok()
+    }
+
+    pub(crate) fn set_current(&self, scene_index: usize) {
+        self.current.store(scene_index as i32, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,1092 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use atomic_float::AtomicF32;
+use nih_plug::params::persist::PersistentField;
+use nih_plug::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::FRAC_PI_4;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::compressor::{Compressor, CompressorParams};
+use crate::delay::{DelayDisplay, StereoDelay, MAX_DELAY_MS};
+use crate::eq::{EqControl, EqParams, Equalizer, NUM_EQ_CONTROLS};
+use crate::limiter::{Limiter, LimiterMode};
+use crate::{MAX_SENDS, NUM_INPUTS};
+
+/// How long muting, unmuting and soloing fade, independently of the gain smoothing time.
+const MUTE_FADE_MS: f32 = 10.0;
+/// How quickly talkback fades in and the program gets dimmed when the engineer starts talking.
+const TALK_ATTACK_MS: f32 = 10.0;
+/// How quickly talkback fades out and the program comes back once the engineer stops talking.
+const TALK_RELEASE_MS: f32 = 150.0;
+
+/// The state shared by all sends for the current block.
+#[derive(Debug, Clone, Copy)]
+pub struct SendContext {
+    pub sample_rate: f32,
+    pub smoothing_time_ms: f32,
+    /// Whether any of the active sends is soloed.
+    pub any_solo: bool,
+    /// Whether any of the active sends is soloed by anything but a scene recall. Recall safe sends
+    /// are only silenced by these solos.
+    pub any_manual_solo: bool,
+    /// Whether the engineer is talking, either through the talk parameter or through MIDI.
+    pub talk: bool,
+    /// How much the program is dimmed on sends that receive talkback, in decibels.
+    pub talkback_dim_db: f32,
+}
+
+/// The parameters for a single stereo send. These are nested as an array in
+/// `MonitoringSenderParams`, so the IDs get the send's number appended (`gain_1`, `gain_2`, ...).
+#[derive(Params)]
+pub struct SendParams {
+    /// What's connected to this send. This decides some defaults, like whether the limiter is on.
+    #[id = "type"]
+    pub send_type: EnumParam<SendType>,
+    /// The send's master gain, applied after the inputs have been mixed.
+    #[id = "gain"]
+    pub gain: FloatParam,
+    /// Shifts the stereo image towards the left (-1) or the right (1).
+    #[id = "pan"]
+    pub pan: FloatParam,
+    #[id = "pan_law"]
+    pub pan_law: EnumParam<PanLaw>,
+    /// The side signal's level: 0% is a mono sum, 100% leaves the image alone and 200% widens it.
+    #[id = "width"]
+    pub width: FloatParam,
+    /// Fold the send down to mono, for wedges. Centered signals keep their level, like with a width
+    /// of 0%.
+    #[id = "mono"]
+    pub mono: BoolParam,
+    #[id = "mute"]
+    pub mute: BoolParam,
+    /// Solo in place: while any send is soloed, all sends that aren't soloed are silenced.
+    #[id = "solo"]
+    pub solo: BoolParam,
+    /// Delays the send to align wedges and delay towers with the sound from the stage. Stored in
+    /// milliseconds, but displayed in the globally selected delay unit.
+    #[id = "delay"]
+    pub delay: FloatParam,
+
+    /// Whether this send receives talkback. The program on the send is dimmed while talking.
+    #[id = "talkback"]
+    pub talkback: BoolParam,
+    #[id = "talkback_level"]
+    pub talkback_level: FloatParam,
+    /// Whether this send hears the click.
+    #[id = "click"]
+    pub click: BoolParam,
+    #[id = "click_level"]
+    pub click_level: FloatParam,
+
+    /// This send's row of the input matrix. The first input is the main input, the others are the
+    /// stereo aux inputs.
+    #[nested(array, group = "Input")]
+    pub inputs: [InputParams; NUM_INPUTS],
+
+    #[nested(group = "EQ")]
+    pub eq: EqParams,
+    #[nested(group = "Compressor")]
+    pub compressor: CompressorParams,
+
+    #[id = "limiter"]
+    pub limiter: EnumParam<LimiterMode>,
+    /// The limiter's ceiling in dBTP.
+    #[id = "ceiling"]
+    pub limiter_ceiling: FloatParam,
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendType {
+    #[id = "iem"]
+    #[name = "In-Ears"]
+    InEars,
+    #[id = "wedge"]
+    #[name = "Wedge"]
+    Wedge,
+    /// FOH, delay towers or any other PA speakers.
+    #[id = "speaker"]
+    #[name = "Speaker"]
+    Speaker,
+}
+
+/// The level of one input in a send's mix.
+#[derive(Params)]
+pub struct InputParams {
+    #[id = "level"]
+    pub level: FloatParam,
+}
+
+/// How loud a centered signal is compared to a signal panned hard to one side.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanLaw {
+    /// Centered signals stay at unity gain, panning only attenuates the opposite channel.
+    #[id = "0db"]
+    #[name = "0 dB"]
+    ZeroDb,
+    /// Constant power panning.
+    #[id = "3db"]
+    #[name = "-3 dB"]
+    MinusThreeDb,
+    /// Linear panning, both channels add up to unity gain.
+    #[id = "6db"]
+    #[name = "-6 dB"]
+    MinusSixDb,
+}
+
+impl PanLaw {
+    /// The left and right channel gains for a pan position in `[-1, 1]`.
+    pub fn gains(self, pan: f32) -> (f32, f32) {
+        match self {
+            PanLaw::ZeroDb => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+            PanLaw::MinusThreeDb => {
+                let angle = (pan + 1.0) * FRAC_PI_4;
+                (angle.cos(), angle.sin())
+            }
+            PanLaw::MinusSixDb => ((1.0 - pan) * 0.5, (1.0 + pan) * 0.5),
+        }
+    }
+}
+
+/// The send parameters that can be controlled from outside of the host, like through MIDI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SendControl {
+    Gain,
+    Pan,
+    Width,
+    Mono,
+    Mute,
+    Solo,
+    Delay,
+    TalkbackLevel,
+    ClickLevel,
+    /// The level of an input in the send's mix, indexed from 0.
+    InputLevel(usize),
+    /// An EQ setting. Unlike the other controls these change right away instead of ramping.
+    Eq(EqControl),
+}
+
+/// The number of [`SendControl`]s, counting every input's level and every EQ setting.
+pub const NUM_SEND_CONTROLS: usize = 9 + NUM_INPUTS + NUM_EQ_CONTROLS;
+
+/// A [`SendControl`]'s parameter.
+pub enum ControlParam<'a> {
+    Float(&'a FloatParam),
+    Bool(&'a BoolParam),
+}
+
+impl SendControl {
+    /// Every control, in a stable order matching [`index()`][Self::index()].
+    pub fn all() -> impl Iterator<Item = SendControl> {
+        [
+            SendControl::Gain,
+            SendControl::Pan,
+            SendControl::Width,
+            SendControl::Mono,
+            SendControl::Mute,
+            SendControl::Solo,
+            SendControl::Delay,
+            SendControl::TalkbackLevel,
+            SendControl::ClickLevel,
+        ]
+        .into_iter()
+        .chain((0..NUM_INPUTS).map(SendControl::InputLevel))
+        .chain(EqControl::all().map(SendControl::Eq))
+    }
+
+    /// A unique index below [`NUM_SEND_CONTROLS`], or `None` for an input or EQ band that doesn't
+    /// exist.
+    pub fn index(self) -> Option<usize> {
+        match self {
+            SendControl::Gain => Some(0),
+            SendControl::Pan => Some(1),
+            SendControl::Width => Some(2),
+            SendControl::Mono => Some(3),
+            SendControl::Mute => Some(4),
+            SendControl::Solo => Some(5),
+            SendControl::Delay => Some(6),
+            SendControl::TalkbackLevel => Some(7),
+            SendControl::ClickLevel => Some(8),
+            SendControl::InputLevel(input_index) if input_index < NUM_INPUTS => {
+                Some(9 + input_index)
+            }
+            SendControl::InputLevel(_) => None,
+            SendControl::Eq(eq_control) => Some(9 + NUM_INPUTS + eq_control.index()?),
+        }
+    }
+
+    pub fn name(self) -> String {
+        let name = match self {
+            SendControl::Gain => "Gain",
+            SendControl::Pan => "Pan",
+            SendControl::Width => "Width",
+            SendControl::Mono => "Mono",
+            SendControl::Mute => "Mute",
+            SendControl::Solo => "Solo",
+            SendControl::Delay => "Delay",
+            SendControl::TalkbackLevel => "Talkback Level",
+            SendControl::ClickLevel => "Click Level",
+            SendControl::InputLevel(input_index) => {
+                return format!("Input {} Level", input_index + 1)
+            }
+            SendControl::Eq(eq_control) => return format!("EQ {}", eq_control.name()),
+        };
+        name.to_string()
+    }
+
+    /// The parameter this control changes, or `None` for an input or EQ band that doesn't exist.
+    pub fn param(self, params: &SendParams) -> Option<ControlParam<'_>> {
+        Some(match self {
+            SendControl::Gain => ControlParam::Float(&params.gain),
+            SendControl::Pan => ControlParam::Float(&params.pan),
+            SendControl::Width => ControlParam::Float(&params.width),
+            SendControl::Mono => ControlParam::Bool(&params.mono),
+            SendControl::Mute => ControlParam::Bool(&params.mute),
+            SendControl::Solo => ControlParam::Bool(&params.solo),
+            SendControl::Delay => ControlParam::Float(&params.delay),
+            SendControl::TalkbackLevel => ControlParam::Float(&params.talkback_level),
+            SendControl::ClickLevel => ControlParam::Float(&params.click_level),
+            SendControl::InputLevel(input_index) => {
+                ControlParam::Float(&params.inputs.get(input_index)?.level)
+            }
+            SendControl::Eq(eq_control) => return eq_control.param(&params.eq),
+        })
+    }
+}
+
+impl ControlParam<'_> {
+    /// The parameter's plain value, with switches as 0 or 1.
+    pub fn value(&self) -> f32 {
+        match self {
+            ControlParam::Float(param) => param.value(),
+            ControlParam::Bool(param) => param.value() as u8 as f32,
+        }
+    }
+
+    /// The plain value for a normalized value, with switches as 0 or 1.
+    pub fn preview_plain(&self, normalized: f32) -> f32 {
+        match self {
+            ControlParam::Float(param) => param.preview_plain(normalized),
+            ControlParam::Bool(param) => param.preview_plain(normalized) as u8 as f32,
+        }
+    }
+
+    /// The normalized value for a plain value, with switches as 0 or 1.
+    pub fn preview_normalized(&self, plain: f32) -> f32 {
+        match self {
+            ControlParam::Float(param) => param.preview_normalized(plain),
+            ControlParam::Bool(param) => param.preview_normalized(plain >= 0.5),
+        }
+    }
+
+    pub fn unmodulated_normalized_value(&self) -> f32 {
+        match self {
+            ControlParam::Float(param) => param.unmodulated_normalized_value(),
+            ControlParam::Bool(param) => param.unmodulated_normalized_value(),
+        }
+    }
+}
+
+/// The controls the audio thread overrides through MIDI, remote control and scene recalls. These
+/// are forwarded to the host's parameters through [`HostSync`][crate::host::HostSync]. Until the
+/// host has caught up they're saved with the project, and remote clients are shown these values
+/// instead of the parameters'. The audio thread publishes its overrides here once per block.
+pub struct SharedOverrides([[SharedOverride; NUM_SEND_CONTROLS]; MAX_SENDS]);
+
+/// An [`Override`]'s values, the plain values are both NaN if the control isn't overridden.
+struct SharedOverride {
+    value: AtomicF32,
+    param_value: AtomicF32,
+    recalled: AtomicBool,
+    /// The normalized value last sent to the host, NaN if none was sent for this override.
+    forwarded: AtomicF32,
+}
+
+/// An override as it's saved with the project.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SavedOverride {
+    send_index: usize,
+    control: SendControl,
+    value: f32,
+    param_value: f32,
+    #[serde(default)]
+    recalled: bool,
+}
+
+impl Default for SharedOverrides {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| {
+            std::array::from_fn(|_| SharedOverride {
+                value: AtomicF32::new(f32::NAN),
+                param_value: AtomicF32::new(f32::NAN),
+                recalled: AtomicBool::new(false),
+                forwarded: AtomicF32::new(f32::NAN),
+            })
+        }))
+    }
+}
+
+impl SharedOverrides {
+    /// The normalized value a control is overridden with, if its parameter hasn't changed since.
+    pub fn normalized(
+        &self,
+        send_index: usize,
+        control: SendControl,
+        params: &SendParams,
+    ) -> Option<f32> {
+        let shared = self.0.get(send_index)?.get(control.index()?)?;
+        let param = control.param(params)?;
+        let value = shared.value.load(Ordering::Relaxed);
+        (shared.param_value.load(Ordering::Relaxed) == param.value())
+            .then(|| param.preview_normalized(value))
+    }
+
+    /// The normalized value that's actually heard, either the override's or the parameter's.
+    pub fn effective_normalized(
+        &self,
+        send_index: usize,
+        control: SendControl,
+        params: &SendParams,
+    ) -> f32 {
+        self.normalized(send_index, control, params)
+            .unwrap_or_else(|| {
+                control
+                    .param(params)
+                    .map_or(0.0, |param| param.unmodulated_normalized_value())
+            })
+    }
+
+    /// The normalized value last sent to the host for the control's current override.
+    pub fn forwarded(&self, send_index: usize, control: SendControl) -> Option<f32> {
+        let shared = self.0.get(send_index)?.get(control.index()?)?;
+        let forwarded = shared.forwarded.load(Ordering::Relaxed);
+        (!forwarded.is_nan()).then_some(forwarded)
+    }
+
+    /// Remember the normalized value sent to the host for the control's current override.
+    pub fn set_forwarded(&self, send_index: usize, control: SendControl, normalized: f32) {
+        let shared = control
+            .index()
+            .and_then(|control_index| self.0.get(send_index)?.get(control_index));
+        if let Some(shared) = shared {
+            shared.forwarded.store(normalized, Ordering::Relaxed);
+        }
+    }
+
+    fn store(&self, send_index: usize, control_index: usize, value: Option<Override>) {
+        let Some(shared) = self
+            .0
+            .get(send_index)
+            .and_then(|send| send.get(control_index))
+        else {
+            return;
+        };
+        let (value, param_value, recalled) = value.map_or((f32::NAN, f32::NAN, false), |value| {
+            (value.value, value.param_value, value.recalled)
+        });
+        shared.value.store(value, Ordering::Relaxed);
+        shared.param_value.store(param_value, Ordering::Relaxed);
+        shared.recalled.store(recalled, Ordering::Relaxed);
+        if value.is_nan() {
+            shared.forwarded.store(f32::NAN, Ordering::Relaxed);
+        }
+    }
+
+    fn load(&self, send_index: usize, control_index: usize) -> Option<Override> {
+        let shared = self.0.get(send_index)?.get(control_index)?;
+        let value = shared.value.load(Ordering::Relaxed);
+        let param_value = shared.param_value.load(Ordering::Relaxed);
+        (!value.is_nan() && !param_value.is_nan()).then(|| Override {
+            value,
+            param_value,
+            recalled: shared.recalled.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl<'a> PersistentField<'a, Vec<SavedOverride>> for SharedOverrides {
+    fn set(&self, new_value: Vec<SavedOverride>) {
+        for send_index in 0..MAX_SENDS {
+            for control_index in 0..NUM_SEND_CONTROLS {
+                self.store(send_index, control_index, None);
+            }
+        }
+        for saved in new_value {
+            if let Some(control_index) = saved.control.index() {
+                let value = Override {
+                    value: saved.value,
+                    param_value: saved.param_value,
+                    recalled: saved.recalled,
+                };
+                self.store(saved.send_index, control_index, Some(value));
+            }
+        }
+    }
+
+    fn map<F, R>(&self, f: F) -> R
+    where
+        F: Fn(&Vec<SavedOverride>) -> R,
+    {
+        let saved = (0..MAX_SENDS)
+            .flat_map(|send_index| {
+                SendControl::all().filter_map(move |control| {
+                    let value = self.load(send_index, control.index()?)?;
+                    Some(SavedOverride {
+                        send_index,
+                        control,
+                        value: value.value,
+                        param_value: value.param_value,
+                        recalled: value.recalled,
+                    })
+                })
+            })
+            .collect();
+        f(&saved)
+    }
+}
+
+impl SendParams {
+    pub fn new(delay_display: Arc<DelayDisplay>) -> Self {
+        Self {
+            send_type: EnumParam::new("Type", SendType::InEars),
+            gain: gain_param("Gain", 0.0),
+            pan: FloatParam::new(
+                "Pan",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_panning())
+            .with_string_to_value(formatters::s2v_f32_panning()),
+            pan_law: EnumParam::new("Pan Law", PanLaw::ZeroDb),
+            width: FloatParam::new("Width", 1.0, FloatRange::Linear { min: 0.0, max: 2.0 })
+                .with_unit("%")
+                .with_value_to_string(formatters::v2s_f32_percentage(0))
+                .with_string_to_value(formatters::s2v_f32_percentage()),
+            mono: BoolParam::new("Mono", false),
+            mute: BoolParam::new("Mute", false),
+            solo: BoolParam::new("Solo", false),
+            delay: FloatParam::new(
+                "Delay",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: MAX_DELAY_MS,
+                },
+            )
+            .with_step_size(0.01)
+            .with_value_to_string({
+                let delay_display = delay_display.clone();
+                Arc::new(move |delay_ms: f32| delay_display.format(delay_ms))
+            })
+            .with_string_to_value(Arc::new(move |string: &str| delay_display.parse(string))),
+            talkback: BoolParam::new("Talkback", false),
+            talkback_level: gain_param("Talkback Level", 0.0),
+            click: BoolParam::new("Click", false),
+            click_level: gain_param("Click Level", -12.0),
+            inputs: std::array::from_fn(|input_index| InputParams {
+                // Only the main input is heard by default, the aux inputs have to be mixed in
+                level: gain_param("Level", if input_index == 0 { 0.0 } else { MIN_GAIN_DB }),
+            }),
+            eq: EqParams::default(),
+            compressor: CompressorParams::default(),
+            limiter: EnumParam::new("Limiter", LimiterMode::Auto),
+            limiter_ceiling: FloatParam::new(
+                "Limiter Ceiling",
+                -1.0,
+                FloatRange::Linear {
+                    min: -20.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dBTP")
+            .with_step_size(0.1),
+        }
+    }
+}
+
+/// The lowest gain that can be set, treated as silence.
+const MIN_GAIN_DB: f32 = -144.0;
+
+/// A gain parameter going from silence up to +12 dB.
+fn gain_param(name: &str, default_db: f32) -> FloatParam {
+    FloatParam::new(
+        name,
+        util::db_to_gain(default_db),
+        FloatRange::Skewed {
+            min: util::db_to_gain(MIN_GAIN_DB),
+            max: util::db_to_gain(12.0),
+            factor: FloatRange::gain_skew_factor(-24.0, 12.0),
+        },
+    )
+    .with_unit(" dB")
+    .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+    .with_string_to_value(formatters::s2v_f32_gain_to_db())
+}
+
+/// The audio thread state for a single send.
+pub struct SendState {
+    input_levels: [Ramp; NUM_INPUTS],
+    eq: Equalizer,
+    compressor: Compressor,
+    /// Fades the compressor in (1) and out (0) when it gets switched on or off.
+    compressor_amount: Ramp,
+    gain: Ramp,
+    pan: Ramp,
+    width: Ramp,
+    /// Fades between the stereo signal (0) and the mono fold-down (1).
+    mono: Ramp,
+    /// Fades the send out (0) and in (1) for mutes and solos. This is separate from the gain, so
+    /// muting never touches the mix settings.
+    audible: Ramp,
+    /// The talkback level, zero while not talking.
+    talkback: Ramp,
+    /// The gain applied to the program while talking.
+    dim: Ramp,
+    /// The click level, zero if the send doesn't hear the click.
+    click: Ramp,
+    delay: StereoDelay,
+    limiter: Limiter,
+    /// Fades the limiter in (1) and out (0) when it gets switched on or off.
+    limiter_amount: Ramp,
+    /// The limiter's ceiling as a linear gain.
+    limiter_ceiling: f32,
+    pan_law: PanLaw,
+    /// The channel gains for `pan_gains_pan`, so they're only recomputed while the pan moves.
+    pan_gains: (f32, f32),
+    pan_gains_pan: f32,
+    /// Values set through MIDI, remote control and scene recalls, which take precedence over the
+    /// parameters.
+    overrides: Overrides,
+    recall_safe: bool,
+}
+
+impl Default for SendState {
+    fn default() -> Self {
+        Self {
+            input_levels: std::array::from_fn(|_| Ramp::new(1.0)),
+            eq: Equalizer::default(),
+            compressor: Compressor::default(),
+            compressor_amount: Ramp::new(0.0),
+            gain: Ramp::new(1.0),
+            pan: Ramp::new(0.0),
+            width: Ramp::new(1.0),
+            mono: Ramp::new(0.0),
+            audible: Ramp::new(1.0),
+            talkback: Ramp::new(0.0),
+            dim: Ramp::new(1.0),
+            click: Ramp::new(0.0),
+            delay: StereoDelay::default(),
+            limiter: Limiter::default(),
+            limiter_amount: Ramp::new(1.0),
+            limiter_ceiling: 1.0,
+            pan_law: PanLaw::ZeroDb,
+            pan_gains: PanLaw::ZeroDb.gains(0.0),
+            pan_gains_pan: 0.0,
+            overrides: Overrides::default(),
+            recall_safe: false,
+        }
+    }
+}
+
+impl SendState {
+    /// Allocate the delay lines for this sample rate. Must not be called from the audio thread.
+    pub fn resize(&mut self, sample_rate: f32) {
+        self.delay.resize(sample_rate);
+        self.limiter.resize(sample_rate);
+    }
+
+    /// Jump straight to the current parameter values without smoothing, and clear the EQ's, the
+    /// compressor's, the delay line's and the limiter's state.
+    pub fn reset(&mut self, params: &SendParams, context: &SendContext) {
+        let overrides = &self.overrides;
+        for (input_index, input_level) in self.input_levels.iter_mut().enumerate() {
+            input_level.reset(overrides.value(params, SendControl::InputLevel(input_index)));
+        }
+        self.eq.reset(
+            |control| overrides.value(params, SendControl::Eq(control)),
+            context.sample_rate,
+        );
+        self.compressor
+            .update(&params.compressor, context.sample_rate);
+        self.compressor.reset();
+        self.compressor_amount.reset(compressor_amount(params));
+        self.gain.reset(overrides.value(params, SendControl::Gain));
+        self.pan.reset(overrides.value(params, SendControl::Pan));
+        self.width
+            .reset(overrides.value(params, SendControl::Width));
+        self.mono.reset(mono_amount(params, overrides));
+        self.audible
+            .reset(audible_amount(params, overrides, self.recall_safe, context));
+        let (talkback, dim) = talkback_amounts(params, overrides, context);
+        self.talkback.reset(talkback);
+        self.dim.reset(dim);
+        self.click.reset(click_amount(params, overrides));
+        self.delay
+            .reset(delay_samples(params, overrides, context.sample_rate));
+        self.limiter.reset();
+        self.limiter_amount.reset(limiter_amount(params));
+        self.limiter_ceiling = util::db_to_gain(params.limiter_ceiling.value());
+        self.pan_law = params.pan_law.value();
+        self.pan_gains_pan = self.pan.previous();
+        self.pan_gains = self.pan_law.gains(self.pan_gains_pan);
+    }
+
+    /// Pick up parameter changes. Called at the start of every block, and whenever a control is
+    /// overridden in the middle of a block.
+    pub fn update(&mut self, params: &SendParams, context: &SendContext) {
+        self.update_with_fade(params, context, None);
+    }
+
+    /// Override controls with normalized values and fade to them over `fade_ms`, like when
+    /// recalling a scene.
+    pub fn fade_to(
+        &mut self,
+        params: &SendParams,
+        values: impl IntoIterator<Item = (SendControl, f32)>,
+        fade_ms: f32,
+        context: &SendContext,
+    ) {
+        for (control, normalized) in values {
+            self.overrides.set(params, control, normalized, true);
+        }
+        self.update_with_fade(params, context, Some(fade_ms));
+    }
+
+    /// Like [`update()`][Self::update()], but the mix levels and switches that changed ramp to
+    /// their new values over `fade_ms` instead of the usual smoothing and mute fade times.
+    fn update_with_fade(
+        &mut self,
+        params: &SendParams,
+        context: &SendContext,
+        fade_ms: Option<f32>,
+    ) {
+        let SendContext {
+            sample_rate,
+            smoothing_time_ms,
+            ..
+        } = *context;
+        let mix_ms = fade_ms.unwrap_or(smoothing_time_ms);
+        let switch_ms = fade_ms.unwrap_or(MUTE_FADE_MS);
+        let overrides = &self.overrides;
+        for (input_index, input_level) in self.input_levels.iter_mut().enumerate() {
+            input_level.update(
+                SmoothingStyle::Logarithmic(mix_ms),
+                sample_rate,
+                overrides.value(params, SendControl::InputLevel(input_index)),
+            );
+        }
+        self.eq.update(
+            |control| overrides.value(params, SendControl::Eq(control)),
+            sample_rate,
+            mix_ms,
+            switch_ms,
+        );
+        self.compressor.update(&params.compressor, sample_rate);
+        self.compressor_amount.update(
+            SmoothingStyle::Linear(MUTE_FADE_MS),
+            sample_rate,
+            compressor_amount(params),
+        );
+        self.gain.update(
+            SmoothingStyle::Logarithmic(mix_ms),
+            sample_rate,
+            overrides.value(params, SendControl::Gain),
+        );
+        self.pan.update(
+            SmoothingStyle::Linear(mix_ms),
+            sample_rate,
+            overrides.value(params, SendControl::Pan),
+        );
+        self.width.update(
+            SmoothingStyle::Linear(mix_ms),
+            sample_rate,
+            overrides.value(params, SendControl::Width),
+        );
+        self.mono.update(
+            SmoothingStyle::Linear(mix_ms),
+            sample_rate,
+            mono_amount(params, overrides),
+        );
+        self.audible.update(
+            SmoothingStyle::Linear(switch_ms),
+            sample_rate,
+            audible_amount(params, overrides, self.recall_safe, context),
+        );
+        let (talkback, dim) = talkback_amounts(params, overrides, context);
+        let talk_ramp_ms = if talkback > 0.0 {
+            TALK_ATTACK_MS
+        } else {
+            TALK_RELEASE_MS
+        };
+        self.talkback
+            .update(SmoothingStyle::Linear(talk_ramp_ms), sample_rate, talkback);
+        self.dim
+            .update(SmoothingStyle::Linear(talk_ramp_ms), sample_rate, dim);
+        self.click.update(
+            SmoothingStyle::Linear(switch_ms),
+            sample_rate,
+            click_amount(params, overrides),
+        );
+        self.delay
+            .set_delay(delay_samples(params, overrides, sample_rate));
+        self.limiter_amount.update(
+            SmoothingStyle::Linear(MUTE_FADE_MS),
+            sample_rate,
+            limiter_amount(params),
+        );
+        self.limiter_ceiling = util::db_to_gain(params.limiter_ceiling.value());
+
+        let pan_law = params.pan_law.value();
+        if pan_law != self.pan_law {
+            self.pan_law = pan_law;
+            self.pan_gains = pan_law.gains(self.pan_gains_pan);
+        }
+    }
+
+    /// Mix a stereo frame from every input into the send and process it. The mono `talkback` and
+    /// `click` signals are added after the send's gain, so musicians can hear them even when their
+    /// send is turned down. Muting the send or soloing another one silences the click, talkback
+    /// still gets through.
+    pub fn process(
+        &mut self,
+        inputs: &[(f32, f32); NUM_INPUTS],
+        talkback: f32,
+        click: f32,
+    ) -> (f32, f32) {
+        let (mut left, mut right) = (0.0, 0.0);
+        for (input_level, (input_l, input_r)) in self.input_levels.iter().zip(inputs.iter()) {
+            let level = input_level.next();
+            left += input_l * level;
+            right += input_r * level;
+        }
+
+        let dim = self.dim.next();
+        let (left, right) = self.eq.process(left * dim, right * dim);
+        let compressor_amount = self.compressor_amount.next();
+        let (left, right) = self.compressor.process(left, right, compressor_amount);
+
+        // Width is applied on a mid/side matrix, with the mid channel being the average of both
+        // channels so a width of 0% doesn't change the level of centered signals
+        let width = self.width.next();
+        let mid = (left + right) * 0.5;
+        let side = (left - right) * 0.5 * width;
+        let (left, right) = (mid + side, mid - side);
+
+        // The mono fold-down is the same mid channel as a width of 0%, so centered signals like the
+        // lead vocal or the kick keep their level when a wedge gets switched to mono
+        let mono = self.mono.next();
+        let (left, right) = if mono > 0.0 {
+            let mid = (left + right) * 0.5;
+            (left + (mid - left) * mono, right + (mid - right) * mono)
+        } else {
+            (left, right)
+        };
+
+        let pan = self.pan.next();
+        if pan != self.pan_gains_pan {
+            self.pan_gains_pan = pan;
+            self.pan_gains = self.pan_law.gains(pan);
+        }
+
+        let audible = self.audible.next();
+        let gain = self.gain.next() * audible;
+        let (pan_l, pan_r) = self.pan_gains;
+        let cue = talkback * self.talkback.next() + click * self.click.next() * audible;
+        let (left, right) = self
+            .delay
+            .process(left * gain * pan_l + cue, right * gain * pan_r + cue);
+
+        let limiter_amount = self.limiter_amount.next();
+        self.limiter
+            .process(left, right, self.limiter_ceiling, limiter_amount)
+    }
+
+    /// Set a control to a normalized value, taking precedence over its parameter until the
+    /// parameter changes. [`update()`][Self::update()] needs to be called afterwards.
+    pub fn set_override(&mut self, params: &SendParams, control: SendControl, normalized: f32) {
+        self.overrides.set(params, control, normalized, false);
+    }
+
+    /// Recall safe sends aren't silenced when a scene recall solos another send.
+    pub fn set_recall_safe(&mut self, recall_safe: bool) {
+        self.recall_safe = recall_safe;
+    }
+
+    /// Drop the overrides whose parameters have changed since, and publish the remaining ones.
+    /// Returns whether the host still needs to catch up with any of them.
+    pub fn publish_overrides(
+        &mut self,
+        params: &SendParams,
+        shared: &SharedOverrides,
+        send_index: usize,
+    ) -> bool {
+        self.overrides
+            .drop_outdated(params, |control| shared.forwarded(send_index, control));
+        for (control_index, value) in self.overrides.0.iter().enumerate() {
+            shared.store(send_index, control_index, *value);
+        }
+
+        self.overrides
+            .0
+            .iter()
+            .flatten()
+            .any(|value| value.value != value.param_value)
+    }
+
+    /// Pick up the overrides saved with the project. [`reset()`][Self::reset()] needs to be called
+    /// afterwards.
+    pub fn restore_overrides(&mut self, shared: &SharedOverrides, send_index: usize) {
+        for (control_index, value) in self.overrides.0.iter_mut().enumerate() {
+            *value = shared.load(send_index, control_index);
+        }
+    }
+
+    /// Whether the send is soloed, either by its parameter or by an override.
+    pub fn solo(&self, params: &SendParams) -> bool {
+        self.overrides.switch(params, SendControl::Solo)
+    }
+
+    /// Whether the send is soloed by its parameter or by an override that isn't from a scene
+    /// recall. A recalled solo stays an override until its parameter is changed by something else
+    /// than the host catching up with it.
+    pub fn manual_solo(&self, params: &SendParams) -> bool {
+        self.solo(params) && !self.overrides.recalled(params, SendControl::Solo)
+    }
+
+    /// The compressor's current gain reduction in decibels, as a negative number.
+    pub fn gain_reduction_db(&self) -> f32 {
+        self.compressor.gain_reduction_db() * self.compressor_amount.previous()
+    }
+
+    /// Pass a frame through without any processing. It's still delayed by the limiter's lookahead,
+    /// so it stays aligned with the other sends and the main output.
+    pub fn process_unity(&mut self, left: f32, right: f32) -> (f32, f32) {
+        self.limiter.process(left, right, self.limiter_ceiling, 0.0)
+    }
+}
+
+fn mono_amount(params: &SendParams, overrides: &Overrides) -> f32 {
+    if overrides.switch(params, SendControl::Mono) {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn delay_samples(params: &SendParams, overrides: &Overrides, sample_rate: f32) -> usize {
+    (overrides.value(params, SendControl::Delay) / 1000.0 * sample_rate).round() as usize
+}
+
+/// The talkback level and the program gain for this send.
+fn talkback_amounts(
+    params: &SendParams,
+    overrides: &Overrides,
+    context: &SendContext,
+) -> (f32, f32) {
+    if context.talk && params.talkback.value() {
+        (
+            overrides.value(params, SendControl::TalkbackLevel),
+            util::db_to_gain(context.talkback_dim_db),
+        )
+    } else {
+        (0.0, 1.0)
+    }
+}
+
+fn click_amount(params: &SendParams, overrides: &Overrides) -> f32 {
+    if params.click.value() {
+        overrides.value(params, SendControl::ClickLevel)
+    } else {
+        0.0
+    }
+}
+
+fn compressor_amount(params: &SendParams) -> f32 {
+    if params.compressor.enabled.value() {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn limiter_amount(params: &SendParams) -> f32 {
+    let enabled = match params.limiter.value() {
+        LimiterMode::Auto => params.send_type.value() == SendType::InEars,
+        LimiterMode::On => true,
+        LimiterMode::Off => false,
+    };
+
+    if enabled {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn audible_amount(
+    params: &SendParams,
+    overrides: &Overrides,
+    recall_safe: bool,
+    context: &SendContext,
+) -> f32 {
+    let any_solo = if recall_safe {
+        context.any_manual_solo
+    } else {
+        context.any_solo
+    };
+    let muted = overrides.switch(params, SendControl::Mute)
+        || (any_solo && !overrides.switch(params, SendControl::Solo));
+    if muted {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+/// A value set from outside of the host. It takes precedence over the parameter until the
+/// parameter itself changes, so automation and the editor always win again.
+#[derive(Debug, Clone, Copy)]
+struct Override {
+    value: f32,
+    /// The parameter's value when the override was set.
+    param_value: f32,
+    /// Whether the override was set by a scene recall.
+    recalled: bool,
+}
+
+/// A send's overridden controls, indexed by [`SendControl::index()`].
+#[derive(Debug, Default, Clone, Copy)]
+struct Overrides([Option<Override>; NUM_SEND_CONTROLS]);
+
+impl Overrides {
+    fn set(&mut self, params: &SendParams, control: SendControl, normalized: f32, recalled: bool) {
+        if let (Some(index), Some(param)) = (control.index(), control.param(params)) {
+            self.0[index] = Some(Override {
+                value: param.preview_plain(normalized),
+                param_value: param.value(),
+                recalled,
+            });
+        }
+    }
+
+    /// The override that takes precedence over the control's parameter, if any.
+    fn active(&self, params: &SendParams, control: SendControl) -> Option<Override> {
+        let param_value = control.param(params)?.value();
+        control
+            .index()
+            .and_then(|index| self.0[index])
+            .filter(|value| value.param_value == param_value)
+    }
+
+    /// The control's plain value, with switches as 0 or 1.
+    fn value(&self, params: &SendParams, control: SendControl) -> f32 {
+        match self.active(params, control) {
+            Some(value) => value.value,
+            None => control.param(params).map_or(0.0, |param| param.value()),
+        }
+    }
+
+    /// Whether the control's value comes from a scene recall.
+    fn recalled(&self, params: &SendParams, control: SendControl) -> bool {
+        self.active(params, control)
+            .is_some_and(|value| value.recalled)
+    }
+
+    fn switch(&self, params: &SendParams, control: SendControl) -> bool {
+        self.value(params, control) >= 0.5
+    }
+
+    /// Forget the overrides that no longer take precedence, or that the parameter has caught up
+    /// with. Otherwise an override would take effect again if its parameter returned to the old
+    /// value. `forwarded` returns the normalized value last sent to the host for a control. While
+    /// a controller keeps moving the host catches up with older values than the override's, those
+    /// don't count as parameter changes. Recalled solos are kept after the host caught up with them,
+    /// so they still aren't mistaken for manual solos, see [`SendState::manual_solo()`].
+    fn drop_outdated(
+        &mut self,
+        params: &SendParams,
+        forwarded: impl Fn(SendControl) -> Option<f32>,
+    ) {
+        for control in SendControl::all() {
+            let (Some(index), Some(param)) = (control.index(), control.param(params)) else {
+                continue;
+            };
+            let param_value = param.value();
+            let Some(value) = &mut self.0[index] else {
+                continue;
+            };
+
+            if value.param_value != param_value {
+                let caught_up = forwarded(control)
+                    .is_some_and(|forwarded| param.preview_plain(forwarded) == param_value);
+                if caught_up {
+                    value.param_value = param_value;
+                } else {
+                    self.0[index] = None;
+                    continue;
+                }
+            }
+            if value.value == param_value && !(value.recalled && control == SendControl::Solo) {
+                self.0[index] = None;
+            }
+        }
+    }
+}
+
+/// A smoothed parameter value. The smoother is only retargeted when the parameter actually changes,
+/// so a slow ramp doesn't start over on every block.
+struct Ramp {
+    smoother: Smoother<f32>,
+    target: f32,
+}
+
+impl Ramp {
+    fn new(value: f32) -> Self {
+        let smoother = Smoother::new(SmoothingStyle::None);
+        smoother.reset(value);
+
+        Self {
+            smoother,
+            target: value,
+        }
+    }
+
+    fn reset(&mut self, value: f32) {
+        self.target = value;
+        self.smoother.reset(value);
+    }
+
+    fn update(&mut self, style: SmoothingStyle, sample_rate: f32, target: f32) {
+        self.smoother.style = style;
+        if target != self.target {
+            self.target = target;
+            self.smoother.set_target(sample_rate, target);
+        }
+    }
+
+    fn next(&self) -> f32 {
+        self.smoother.next()
+    }
+
+    fn previous(&self) -> f32 {
+        self.smoother.previous_value()
+    }
+}
@@ -0,0 +1,91 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Scenes recalled automatically at positions on the host's timeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Setlist {
+    pub enabled: bool,
+    pub unit: PositionUnit,
+    /// The entries don't need to be in order. With multiple entries at the same position, the last
+    /// one is used.
+    pub entries: Vec<SetlistEntry>,
+}
+
+impl Default for Setlist {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            unit: PositionUnit::Bars,
+            entries: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PositionUnit {
+    /// Bars counted from 1, so 1.0 is the start of the timeline and 2.5 is halfway through the
+    /// second bar.
+    Bars,
+    Seconds,
+}
+
+/// Recall a scene from this position on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SetlistEntry {
+    pub position: f64,
+    pub scene_index: usize,
+}
+
+impl Setlist {
+    /// The entry in effect at the transport's position: the last one at or before it. This only
+    /// depends on the position, so jumping around the timeline always ends up with the same entry.
+    /// Returns `None` if no entry has been reached yet or if the host doesn't report the position.
+    pub fn active_entry(&self, transport: &Transport) -> Option<SetlistEntry> {
+        let position = position(transport, self.unit)?;
+        self.entries
+            .iter()
+            .filter(|entry| entry.position <= position)
+            .fold(None, |active: Option<SetlistEntry>, &entry| match active {
+                Some(active) if active.position > entry.position => Some(active),
+                _ => Some(entry),
+            })
+    }
+
+    /// Sort the entries by position, keeping entries at the same position in their order.
+    pub fn sort(&mut self) {
+        self.entries
+            .sort_by(|a, b| a.position.total_cmp(&b.position));
+    }
+}
+
+/// The transport's position in `unit`, if the host provides enough information to compute it.
+fn position(transport: &Transport, unit: PositionUnit) -> Option<f64> {
+    match unit {
+        PositionUnit::Seconds => transport.pos_seconds(),
+        PositionUnit::Bars => {
+            let bar_number = transport.bar_number()? as f64;
+            let beats_into_bar = transport.pos_beats()? - transport.bar_start_pos_beats()?;
+            // Beats are quarter notes
+            let beats_per_bar =
+                transport.time_sig_numerator? as f64 * 4.0 / transport.time_sig_denominator? as f64;
+
+            Some(bar_number + 1.0 + beats_into_bar / beats_per_bar)
+        }
+    }
+}
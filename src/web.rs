@@ -0,0 +1,550 @@
+// Monitoring sender : Sends stereo channel to different outputs at different levels
+// Copyright (C) 2023 Volkmar Kobelt
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use nih_plug::prelude::*;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tungstenite::{Message, WebSocket};
+
+use crate::meters::Meters;
+use crate::remote::RemoteControl;
+use crate::send::SendControl;
+use crate::MonitoringSenderParams;
+
+/// The port suggested when the web server gets enabled.
+pub const DEFAULT_PORT: u16 = 8080;
+/// How often connected pages get sent the current values and meters. This is also how often the
+/// server checks whether it should stop.
+const FEEDBACK_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a client gets to send its request headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// Clients that don't take any data for this long are disconnected, like phones that went to
+/// sleep with the page still open. This also bounds how long stopping the server can take.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The most clients that can be connected at the same time.
+const MAX_CLIENTS: usize = 32;
+/// Requests with longer headers are rejected.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// A web server for musicians' phones on its own thread. `/` lists the active sends, and
+/// `/send/{n}` is a page for controlling only send `n`, numbered from 1. That page connects to a
+/// WebSocket at `/ws/send/{n}` which receives the send's values and meters as JSON every
+/// [`FEEDBACK_INTERVAL`]. The page sends text messages like `gain 0.5` back, with the control's
+/// name and the parameter's normalized value. Changes are passed to the audio thread through
+/// [`RemoteControl`], and from there to the host. The JSON's `host` field tells the page whether
+/// they currently reach the host's parameters, see [`HostSync`][crate::host::HostSync].
+pub struct WebServer {
+    port: u16,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// What the server threads share.
+struct Shared {
+    params: Arc<MonitoringSenderParams>,
+    meters: Arc<Meters>,
+    remote: Arc<RemoteControl>,
+    running: Arc<AtomicBool>,
+}
+
+impl WebServer {
+    /// Listen on `port` on all interfaces and start the server thread.
+    pub fn start(
+        port: u16,
+        params: Arc<MonitoringSenderParams>,
+        meters: Arc<Meters>,
+        remote: Arc<RemoteControl>,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        // Port 0 picks any free port
+        let port = listener.local_addr()?.port();
+        // Polling lets the thread notice when it should stop
+        listener.set_nonblocking(true)?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let shared = Arc::new(Shared {
+            params,
+            meters,
+            remote,
+            running: running.clone(),
+        });
+        let thread = std::thread::Builder::new()
+            .name(String::from("Web server"))
+            .spawn(move || accept_clients(listener, shared))?;
+
+        Ok(Self {
+            port,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for WebServer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Accept connections until the server is stopped. Every client gets its own thread, these are
+/// all joined before returning so no thread outlives the plugin. The clients' timeouts keep this
+/// from taking more than a few seconds.
+fn accept_clients(listener: TcpListener, shared: Arc<Shared>) {
+    let mut clients: Vec<JoinHandle<()>> = Vec::new();
+    while shared.running.load(Ordering::Relaxed) {
+        clients.retain(|client| !client.is_finished());
+
+        match listener.accept() {
+            Ok((stream, _)) if clients.len() < MAX_CLIENTS => {
+                let shared = shared.clone();
+                let client = std::thread::Builder::new()
+                    .name(String::from("Web client"))
+                    .spawn(move || {
+                        if let Err(err) = handle_client(stream, &shared) {
+                            nih_log!("Web client disconnected: {err}");
+                        }
+                    });
+                match client {
+                    Ok(client) => clients.push(client),
+                    Err(err) => nih_error!("Could not start a web client thread: {err}"),
+                }
+            }
+            // The stream is dropped right away if there are too many clients
+            Ok(_) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(FEEDBACK_INTERVAL)
+            }
+            Err(err) => {
+                nih_error!("Web server stopped: {err}");
+                break;
+            }
+        }
+    }
+
+    shared.running.store(false, Ordering::Relaxed);
+    for client in clients {
+        let _ = client.join();
+    }
+}
+
+fn handle_client(mut stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    // The headers are only peeked at so the WebSocket handshake can still read them
+    let Some(request) = peek_request(&stream)? else {
+        return write_response(&mut stream, "400 Bad Request", "text/plain", "Bad request");
+    };
+    let num_sends = shared.meters.num_sends.load(Ordering::Relaxed);
+    let send_index = |prefix: &str| {
+        request
+            .path
+            .strip_prefix(prefix)
+            .and_then(|send_number| send_number.parse::<usize>().ok())
+            .filter(|send_number| (1..=num_sends).contains(send_number))
+            .map(|send_number| send_number - 1)
+    };
+
+    if request.websocket {
+        return match send_index("/ws/send/") {
+            Some(send_index) => {
+                let websocket = tungstenite::accept(stream).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+                })?;
+                run_websocket(websocket, shared, send_index)
+            }
+            None => write_response(&mut stream, "404 Not Found", "text/plain", "Unknown send"),
+        };
+    }
+
+    // Plain HTTP requests are answered and closed, so the peeked headers need to be consumed
+    let mut headers = vec![0; request.header_len];
+    stream.read_exact(&mut headers)?;
+    if request.path == "/" {
+        write_response(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            &index_page(&shared.params, num_sends),
+        )
+    } else if send_index("/send/").is_some() {
+        write_response(&mut stream, "200 OK", "text/html; charset=utf-8", SEND_PAGE)
+    } else {
+        write_response(&mut stream, "404 Not Found", "text/plain", "Not found")
+    }
+}
+
+/// The parts of a request the server cares about.
+struct Request {
+    path: String,
+    /// Whether the client asks to upgrade to a WebSocket connection.
+    websocket: bool,
+    /// The length of the request line and the headers, including the final blank line.
+    header_len: usize,
+}
+
+/// Wait for the complete request headers without consuming them. Returns `None` for malformed or
+/// oversized requests.
+fn peek_request(stream: &TcpStream) -> std::io::Result<Option<Request>> {
+    let mut buffer = [0u8; MAX_REQUEST_SIZE];
+    let started = Instant::now();
+    let header_len = loop {
+        let size = stream.peek(&mut buffer)?;
+        if size == 0 {
+            return Ok(None);
+        }
+        if let Some(pos) = buffer[..size]
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            break pos + 4;
+        }
+        if size == buffer.len() || started.elapsed() > REQUEST_TIMEOUT {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    let headers = String::from_utf8_lossy(&buffer[..header_len]);
+    let mut lines = headers.lines();
+    let Some(path) = lines.next().and_then(|line| line.split_whitespace().nth(1)) else {
+        return Ok(None);
+    };
+    let websocket = lines.any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("upgrade:") && line.contains("websocket")
+    });
+
+    Ok(Some(Request {
+        path: path.to_string(),
+        websocket,
+        header_len,
+    }))
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Exchange values with a send's page until the client disconnects or the server is stopped.
+fn run_websocket(
+    mut websocket: WebSocket<TcpStream>,
+    shared: &Shared,
+    send_index: usize,
+) -> std::io::Result<()> {
+    websocket
+        .get_ref()
+        .set_read_timeout(Some(FEEDBACK_INTERVAL / 2))?;
+
+    let mut last_feedback = Instant::now();
+    while shared.running.load(Ordering::Relaxed) {
+        match websocket.read() {
+            Ok(message) => {
+                if let Ok(text) = message.to_text() {
+                    handle_message(shared, send_index, text);
+                }
+            }
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                return Ok(())
+            }
+            Err(err) => return Err(std::io::Error::other(err.to_string())),
+        }
+
+        if last_feedback.elapsed() >= FEEDBACK_INTERVAL {
+            last_feedback = Instant::now();
+            let feedback = feedback_json(shared, send_index);
+            match websocket.send(Message::text(feedback)) {
+                Ok(()) => (),
+                Err(tungstenite::Error::Io(err))
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "the client stopped receiving",
+                    ))
+                }
+                Err(err) => return Err(std::io::Error::other(err.to_string())),
+            }
+        }
+    }
+
+    let _ = websocket.close(None);
+    Ok(())
+}
+
+/// Apply a `{control} {normalized value}` message from a send's page. The audio thread hears it
+/// right away and forwards it to the host as an automation gesture.
+fn handle_message(shared: &Shared, send_index: usize, text: &str) {
+    let Some((control, value)) = text.trim().split_once(' ') else {
+        return;
+    };
+    let Ok(value) = value.trim().parse::<f32>() else {
+        return;
+    };
+
+    let (control, value) = match control {
+        "gain" => (SendControl::Gain, value),
+        "pan" => (SendControl::Pan, value),
+        "mute" => (SendControl::Mute, (value != 0.0) as u8 as f32),
+        _ => return,
+    };
+    shared.remote.set(send_index, control, value);
+}
+
+fn feedback_json(shared: &Shared, send_index: usize) -> String {
+    let send_params = &shared.params.sends[send_index];
+    let send_meters = &shared.meters.sends[send_index];
+    let peak_db = send_meters
+        .channels
+        .iter()
+        .map(|channel| channel.peak_db.load(Ordering::Relaxed))
+        .fold(f32::NEG_INFINITY, f32::max);
+    // Values set through MIDI or scene recalls may not have reached the parameters yet
+    let overrides = &shared.params.overrides;
+    let gain = overrides.effective_normalized(send_index, SendControl::Gain, send_params);
+    let pan = overrides.effective_normalized(send_index, SendControl::Pan, send_params);
+    let mute = overrides.effective_normalized(send_index, SendControl::Mute, send_params) >= 0.5;
+
+    format!(
+        concat!(
+            r#"{{"name":{},"gain":{},"gainText":{},"pan":{},"panText":{},"#,
+            r#""mute":{},"peak":{},"lufs":{},"host":{}}}"#
+        ),
+        json_string(&shared.params.send_name(send_index)),
+        gain,
+        json_string(&send_params.gain.normalized_value_to_string(gain, true)),
+        pan,
+        json_string(&send_params.pan.normalized_value_to_string(pan, true)),
+        mute,
+        peak_db,
+        send_meters.loudness.short_term_lufs.load(Ordering::Relaxed),
+        shared.params.host.is_connected(),
+    )
+}
+
+/// A JSON string literal for `string`.
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Escape text for use in HTML.
+fn html_escape(string: &str) -> String {
+    string
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Links to every active send's page.
+fn index_page(params: &MonitoringSenderParams, num_sends: usize) -> String {
+    let links: String = (0..num_sends)
+        .map(|send_index| {
+            format!(
+                r#"<li><a href="/send/{}">{}</a></li>"#,
+                send_index + 1,
+                html_escape(&params.send_name(send_index))
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Monitoring Sender</title>
+<style>body{{font-family:sans-serif;background:#181818;color:#eee;margin:1em}}a{{color:#8cf;font-size:1.4em;line-height:2em}}</style>
+</head><body><h1>Monitoring Sender</h1><ul>{links}</ul></body></html>"#
+    )
+}
+
+/// A single send's page. It finds its WebSocket through its own URL.
+const SEND_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Monitoring Sender</title>
+<style>
+body{font-family:sans-serif;background:#181818;color:#eee;margin:1em;text-align:center}
+input[type=range]{width:100%;height:3em}
+#meter{height:1.2em;background:#333;margin:1em 0}
+#peak{height:100%;width:0;background:#5d6}
+button{font-size:1.4em;padding:.5em 2em;margin:1em}
+button.on{background:#c43;color:#fff}
+#status,#host{color:#e93}
+</style></head><body>
+<h1 id="name"></h1>
+<div id="meter"><div id="peak"></div></div>
+<div id="lufs"></div>
+<h2>Level <span id="gainText"></span></h2>
+<input id="gain" type="range" min="0" max="1" step="0.001">
+<h2>Pan <span id="panText"></span></h2>
+<input id="pan" type="range" min="0" max="1" step="0.001">
+<button id="mute">Mute</button>
+<div id="status"></div>
+<div id="host"></div>
+<script>
+const $ = id => document.getElementById(id);
+let socket, touching = {};
+function connect() {
+  socket = new WebSocket("ws://" + location.host + "/ws" + location.pathname);
+  socket.onopen = () => $("status").textContent = "";
+  socket.onmessage = event => {
+    const state = JSON.parse(event.data);
+    document.title = $("name").textContent = state.name;
+    for (const control of ["gain", "pan"]) {
+      if (!touching[control]) $(control).value = state[control];
+      $(control + "Text").textContent = state[control + "Text"];
+    }
+    $("mute").classList.toggle("on", state.mute);
+    $("mute").dataset.on = state.mute ? 1 : 0;
+    $("peak").style.width = Math.min(100, Math.max(0, (state.peak + 60) / 66 * 100)) + "%";
+    $("lufs").textContent = state.lufs.toFixed(1) + " LUFS";
+    $("host").textContent = state.host ? "" :
+      "Changes are heard, but the host only picks them up once the plugin window has been opened.";
+  };
+  socket.onclose = () => {
+    $("status").textContent = "Reconnecting...";
+    setTimeout(connect, 1000);
+  };
+}
+for (const control of ["gain", "pan"]) {
+  const input = $(control);
+  input.addEventListener("pointerdown", () => touching[control] = true);
+  input.addEventListener("pointerup", () => touching[control] = false);
+  input.addEventListener("input", () => socket.send(control + " " + input.value));
+}
+$("mute").addEventListener("click", () => socket.send("mute " + ($("mute").dataset.on == 1 ? 0 : 1)));
+connect();
+</script></body></html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn start_server() -> (WebServer, Arc<RemoteControl>) {
+        let meters = Arc::new(Meters::default());
+        meters.num_sends.store(4, Ordering::Relaxed);
+        let remote = Arc::new(RemoteControl::default());
+        let server = WebServer::start(
+            0,
+            Arc::new(MonitoringSenderParams::default()),
+            meters,
+            remote.clone(),
+        )
+        .unwrap();
+
+        (server, remote)
+    }
+
+    fn connect(server: &WebServer) -> TcpStream {
+        let stream = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        stream
+    }
+
+    fn get(server: &WebServer, path: &str) -> String {
+        let mut stream = connect(server);
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_pages_over_loopback() {
+        let (server, _remote) = start_server();
+
+        let index = get(&server, "/");
+        assert!(index.starts_with("HTTP/1.1 200 OK"), "{index}");
+        assert!(index.contains("/send/4"));
+        assert!(!index.contains("/send/5"));
+
+        let send_page = get(&server, "/send/2");
+        assert!(send_page.starts_with("HTTP/1.1 200 OK"), "{send_page}");
+        assert!(get(&server, "/send/5").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn websocket_over_loopback() {
+        let (server, remote) = start_server();
+        let url = format!("ws://127.0.0.1:{}/ws/send/2", server.port());
+        let (mut websocket, _) = tungstenite::client(url.as_str(), connect(&server)).unwrap();
+
+        let feedback = websocket.read().unwrap();
+        let feedback = feedback.to_text().unwrap();
+        assert!(feedback.contains(r#""gain":"#), "{feedback}");
+        // The editor was never opened
+        assert!(feedback.contains(r#""host":false"#), "{feedback}");
+
+        websocket.send(Message::text("gain 0.25")).unwrap();
+        let started = Instant::now();
+        let gain = loop {
+            if let Some(gain) = remote.take(1, SendControl::Gain) {
+                break gain;
+            }
+            assert!(started.elapsed() < TIMEOUT, "the change never arrived");
+            // Reading keeps the feedback flowing
+            let _ = websocket.read();
+        };
+        assert_eq!(gain, 0.25);
+    }
+}